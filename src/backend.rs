@@ -0,0 +1,224 @@
+use std::{fmt::Debug, path::Path};
+
+/// What [`crate::Git::command`] starts its commands from, in place of running
+/// [`crate::Git::program`] directly. Everything this crate does goes through
+/// here, including the commands run on behalf of [`crate::repo::Repo`] and
+/// [`crate::submodule::Submodule`].
+///
+/// The command has to accept git's arguments, starting with `-C <root>`.
+/// Everything after that (`--no-pager`, the subcommand and its arguments, and
+/// the environment) is added by [`crate::Git::command`].
+pub trait GitBackend: Debug + Send + Sync {
+    fn command(&self, root: &Path) -> bossy::Command;
+}
+
+#[cfg(all(any(test, feature = "test-util"), unix))]
+pub use self::mock::{MockBackend, Response};
+
+#[cfg(all(any(test, feature = "test-util"), unix))]
+mod mock {
+    use super::GitBackend;
+    use std::{
+        io,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Looks up the response for its arguments in the directory named by
+    /// `HIT_MOCK_DIR`, with any options git takes before the subcommand
+    /// stripped off. Each rule has a file per argument holding the pattern
+    /// for it, so arguments are matched one by one rather than joined into a
+    /// string. Calls are recorded with arguments separated by the ASCII unit
+    /// separator and terminated by the record separator, since either could
+    /// turn up in an argument otherwise.
+    const SCRIPT: &str = r#"#!/bin/sh
+while :; do
+    case "$1" in
+        -C|-c) shift 2 ;;
+        --no-pager) shift ;;
+        *) break ;;
+    esac
+done
+for arg in "$@"; do
+    printf '%s\037' "$arg"
+done >> "$HIT_MOCK_DIR/calls"
+printf '\036' >> "$HIT_MOCK_DIR/calls"
+matches() {
+    rule=$1
+    shift
+    [ "$#" -eq "$(cat "$rule/count")" ] || return 1
+    i=0
+    for arg in "$@"; do
+        pattern=$(cat "$rule/$i")
+        case "$arg" in
+            $pattern) ;;
+            *) return 1 ;;
+        esac
+        i=$((i + 1))
+    done
+}
+for rule in "$HIT_MOCK_DIR"/rules/*; do
+    [ -d "$rule" ] || continue
+    if matches "$rule" "$@"; then
+        cat "$rule/stdout"
+        cat "$rule/stderr" >&2
+        exit "$(cat "$rule/code")"
+    fi
+done
+printf 'MockBackend has no response for `git %s`\n' "$*" >&2
+exit 1
+"#;
+
+    /// What a [`MockBackend`] command prints and exits with.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Response {
+        pub stdout: String,
+        pub stderr: String,
+        pub code: i32,
+    }
+
+    impl Response {
+        /// Succeeds after printing `stdout`.
+        pub fn ok(stdout: impl Into<String>) -> Self {
+            Self {
+                stdout: stdout.into(),
+                ..Default::default()
+            }
+        }
+
+        /// Exits with `code` after printing `stderr`.
+        pub fn fail(code: i32, stderr: impl Into<String>) -> Self {
+            Self {
+                stderr: stderr.into(),
+                code,
+                ..Default::default()
+            }
+        }
+    }
+
+    /// A backend that answers commands with scripted responses instead of
+    /// running git, for testing code built on this crate. Commands are still
+    /// real processes (a small shell script), so everything behaves as it
+    /// would with git, errors included.
+    ///
+    /// Responses are matched against the subcommand and its arguments, with
+    /// a shell glob pattern for each argument, so `["rev-parse", "*"]`
+    /// matches `rev-parse HEAD` but not `rev-parse --verify HEAD`, and an
+    /// argument with spaces in it is still just one argument. The first
+    /// matching response wins; commands without one fail with exit code 1.
+    ///
+    /// This is only available on Unix, since the script is run by `/bin/sh`.
+    #[derive(Debug)]
+    pub struct MockBackend {
+        dir: PathBuf,
+        rules: AtomicUsize,
+    }
+
+    impl Drop for MockBackend {
+        fn drop(&mut self) {
+            if let Err(err) = std::fs::remove_dir_all(&self.dir) {
+                log::error!("failed to remove mock backend at {:?}: {}", self.dir, err);
+            }
+        }
+    }
+
+    impl MockBackend {
+        pub fn new() -> io::Result<Self> {
+            use std::os::unix::fs::PermissionsExt as _;
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "hit-mock-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir_all(dir.join("rules"))?;
+            let script = dir.join("git");
+            std::fs::write(&script, SCRIPT)?;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+            std::fs::write(dir.join("calls"), "")?;
+            Ok(Self {
+                dir,
+                rules: AtomicUsize::new(0),
+            })
+        }
+
+        /// Answers commands whose arguments match `args`, one pattern per
+        /// argument, with `response`.
+        pub fn respond(&self, args: &[&str], response: Response) -> io::Result<&Self> {
+            let rule = self
+                .dir
+                .join("rules")
+                .join(format!("{:08}", self.rules.fetch_add(1, Ordering::SeqCst)));
+            std::fs::create_dir(&rule)?;
+            std::fs::write(rule.join("count"), args.len().to_string())?;
+            for (i, pattern) in args.iter().enumerate() {
+                std::fs::write(rule.join(i.to_string()), pattern)?;
+            }
+            std::fs::write(rule.join("stdout"), &response.stdout)?;
+            std::fs::write(rule.join("stderr"), &response.stderr)?;
+            std::fs::write(rule.join("code"), response.code.to_string())?;
+            Ok(self)
+        }
+
+        /// The arguments of every command run so far, in the form responses
+        /// are matched against.
+        pub fn calls(&self) -> io::Result<Vec<Vec<String>>> {
+            std::fs::read_to_string(self.dir.join("calls")).map(|calls| {
+                calls
+                    .split_terminator('\x1e')
+                    .map(|call| {
+                        call.split_terminator('\x1f')
+                            .map(ToOwned::to_owned)
+                            .collect()
+                    })
+                    .collect()
+            })
+        }
+    }
+
+    impl GitBackend for MockBackend {
+        fn command(&self, root: &Path) -> bossy::Command {
+            bossy::Command::impure(self.dir.join("git"))
+                .with_env_var("HIT_MOCK_DIR", &self.dir)
+                .with_arg("-C")
+                .with_arg(root)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::Git;
+    use std::sync::Arc;
+
+    #[test]
+    fn mock_matches_each_argument_separately() {
+        let mock = Arc::new(MockBackend::new().unwrap());
+        mock.respond(&["rev-parse", "HEAD"], Response::ok("abc\n"))
+            .unwrap()
+            .respond(&["commit", "-m", "two words"], Response::ok(""))
+            .unwrap()
+            .respond(&["log", "*"], Response::fail(128, "bad revision\n"))
+            .unwrap();
+        let backend: Arc<dyn GitBackend> = mock.clone();
+        let git = Git::new(Path::new(".")).with_backend(Some(&backend));
+        let run = |args: &[&str]| git.command().with_args(args).run_and_wait_for_output();
+
+        let output = run(&["rev-parse", "HEAD"]).unwrap();
+        assert_eq!(output.stdout(), b"abc\n");
+        run(&["commit", "-m", "two words"]).unwrap();
+        // Splitting the same words up differently doesn't match.
+        assert!(run(&["commit", "-m", "two", "words"]).is_err());
+        let err = run(&["log", "main..feature"]).unwrap_err();
+        assert_eq!(err.code(), Some(128));
+        assert_eq!(err.stderr(), Some(&b"bad revision\n"[..]));
+        assert!(run(&["log", "-1", "main"]).is_err());
+        assert!(run(&["rev-parse", "HEAD", "--"]).is_err());
+
+        let calls = mock.calls().unwrap();
+        assert_eq!(calls.len(), 6);
+        assert_eq!(calls[1], ["commit", "-m", "two words"]);
+        assert_eq!(calls[2], ["commit", "-m", "two", "words"]);
+    }
+}
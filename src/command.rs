@@ -0,0 +1,786 @@
+use crate::{progress::ProgressEvent, OutputMode};
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A flag for cancelling operations from another thread. Clones share the
+/// same flag, so cancelling one cancels them all.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Kills whatever git command is running on behalf of operations using
+    /// this token, and stops them from starting any more.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs `f` on tokio's blocking pool.
+#[cfg(feature = "tokio")]
+pub(crate) async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    joined(tokio::task::spawn_blocking(f).await)
+}
+
+/// Runs `future` as a task of its own, cancelling `token` if the returned
+/// future is dropped first. `future` is expected to use `token`, so that the
+/// task notices, kills whatever git command it's running, and cleans up after
+/// it like any other cancelled operation would.
+#[cfg(feature = "tokio")]
+pub(crate) async fn spawn_cancellable<T: Send + 'static>(
+    token: CancellationToken,
+    future: impl std::future::Future<Output = T> + Send + 'static,
+) -> T {
+    struct CancelOnDrop(Option<CancellationToken>);
+
+    impl Drop for CancelOnDrop {
+        fn drop(&mut self) {
+            if let Some(token) = self.0.take() {
+                token.cancel();
+            }
+        }
+    }
+
+    let mut guard = CancelOnDrop(Some(token));
+    let result = tokio::spawn(future).await;
+    guard.0 = None;
+    joined(result)
+}
+
+#[cfg(feature = "tokio")]
+fn joined<T>(result: Result<T, tokio::task::JoinError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => match err.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            // We never abort our tasks, so this only happens if the runtime
+            // is shutting down.
+            Err(err) => panic!("task failed: {}", err),
+        },
+    }
+}
+
+/// How many times to try commands that talk to a remote, to ride out flaky
+/// networks. Only failures that look transient, like dropped connections or
+/// server errors, are retried; a missing repo or bad credentials fail right
+/// away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to run the command in total, so `1` never retries.
+    pub attempts: u32,
+    /// How long to wait before the first retry. This doubles for each retry
+    /// after that.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff_for(self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(1 << (attempt - 1).min(16))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+/// Takes a guess at whether a command failed due to something that might
+/// not happen again, based on its stderr.
+fn looks_transient(stderr: &str) -> bool {
+    [
+        "Could not resolve host",
+        "Could not resolve hostname",
+        "Connection refused",
+        "Connection reset",
+        "Connection timed out",
+        "Operation timed out",
+        "Network is unreachable",
+        "No route to host",
+        "Failed to connect",
+        "The remote end hung up unexpectedly",
+        "early EOF",
+        "RPC failed",
+        "unexpected disconnect",
+        "The requested URL returned error: 5",
+        "gnutls_handshake() failed",
+        "SSL_read",
+    ]
+    .iter()
+    .any(|pattern| stderr.contains(pattern))
+}
+
+/// Takes a guess at whether a command failed because git wanted to prompt for
+/// credentials, based on its stderr.
+fn looks_like_prompt(stderr: &str) -> bool {
+    [
+        "terminal prompts disabled",
+        "could not read Username",
+        "could not read Password",
+        "Host key verification failed",
+    ]
+    .iter()
+    .any(|pattern| stderr.contains(pattern))
+}
+
+/// What can cut a command short, and what happens when it fails.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Limits {
+    /// This applies to each attempt separately.
+    pub timeout: Option<Duration>,
+    pub cancel: Option<CancellationToken>,
+    pub retry: RetryPolicy,
+}
+
+impl Limits {
+    pub fn retrying(retry: RetryPolicy) -> Self {
+        Self {
+            retry,
+            ..Default::default()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.timeout.is_none() && self.cancel.is_none()
+    }
+
+    fn timed_out(&self, start: Instant) -> bool {
+        self.timeout
+            .map(|timeout| start.elapsed() >= timeout)
+            .unwrap_or(false)
+    }
+
+    /// Why an attempt that began at `start` should be stopped, if it should.
+    fn stopped(&self, start: Instant) -> Option<Stopped> {
+        if self.cancelled() {
+            Some(Stopped::Cancelled)
+        } else if self.timed_out(start) {
+            Some(Stopped::TimedOut(start.elapsed()))
+        } else {
+            None
+        }
+    }
+
+    /// How long an attempt that began at `start` can wait for output before
+    /// checking whether it should be stopped.
+    fn wait(&self, start: Instant) -> Duration {
+        let remaining = self
+            .timeout
+            .map(|timeout| timeout.checked_sub(start.elapsed()).unwrap_or_default());
+        match (remaining, &self.cancel) {
+            (Some(remaining), Some(_)) => remaining.min(CANCEL_POLL_INTERVAL),
+            (Some(remaining), None) => remaining,
+            (None, _) => CANCEL_POLL_INTERVAL,
+        }
+    }
+
+    /// Sleeps for `duration`, unless we get cancelled in the meantime.
+    fn sleep(&self, duration: Duration) -> Result<(), Stopped> {
+        let start = Instant::now();
+        while let Some(remaining) = duration.checked_sub(start.elapsed()) {
+            if self.cancelled() {
+                return Err(Stopped::Cancelled);
+            }
+            if remaining.is_zero() {
+                break;
+            }
+            std::thread::sleep(remaining.min(CANCEL_POLL_INTERVAL));
+        }
+        Ok(())
+    }
+
+    /// The same as [`Limits::sleep`], but without blocking the async runtime.
+    #[cfg(feature = "tokio")]
+    async fn sleep_async(&self, duration: Duration) -> Result<(), Stopped> {
+        let start = Instant::now();
+        while let Some(remaining) = duration.checked_sub(start.elapsed()) {
+            if self.cancelled() {
+                return Err(Stopped::Cancelled);
+            }
+            if remaining.is_zero() {
+                break;
+            }
+            tokio::time::sleep(remaining.min(CANCEL_POLL_INTERVAL)).await;
+        }
+        Ok(())
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map(CancellationToken::is_cancelled)
+            .unwrap_or(false)
+    }
+}
+
+/// Why a command didn't get to finish normally.
+#[derive(Debug)]
+pub(crate) enum Stopped {
+    TimedOut(Duration),
+    Cancelled,
+    /// Every attempt failed in a way that looked transient.
+    GaveUp {
+        attempts: u32,
+        source: bossy::Error,
+    },
+    /// git needed credentials, but wasn't allowed to prompt for them.
+    AuthenticationRequired(bossy::Error),
+}
+
+/// How often to check whether we've been cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where progress from a clone or fetch gets reported, if anywhere.
+pub(crate) type Progress<'a> = Option<&'a mut dyn FnMut(ProgressEvent)>;
+
+/// Lends out `progress` without giving it up, for when it's needed more than
+/// once.
+pub(crate) fn reborrow<'a>(progress: &'a mut Progress<'_>) -> Progress<'a> {
+    match progress {
+        Some(progress) => Some(&mut **progress),
+        None => None,
+    }
+}
+
+pub(crate) trait CommandExt {
+    fn run_and_wait_in(&mut self, mode: OutputMode) -> bossy::Result<()>;
+
+    /// Like [`CommandExt::run_and_wait_in`], but if there's a `progress`
+    /// hook, it gets every progress line git writes to stderr. The command
+    /// needs to have been given `--progress` for git to write any while
+    /// stderr isn't a terminal. Lines that aren't progress are logged, or
+    /// passed through if `mode` is [`OutputMode::Inherit`], so they aren't
+    /// part of the error if the command fails.
+    ///
+    /// The command is killed if it's still running after the timeout or gets
+    /// cancelled, and retried according to the retry policy.
+    ///
+    /// Only git itself is killed, not its whole process group: `bossy`
+    /// doesn't give us the child's PID or a way to spawn it in a group of its
+    /// own. Helpers it started (like `ssh` or `git-remote-https`) keep running
+    /// until they notice git is gone, and so does the thread reading stderr,
+    /// since they hold the other end of the pipe.
+    fn run_and_wait_within(
+        &mut self,
+        mode: OutputMode,
+        progress: Progress<'_>,
+        limits: &Limits,
+    ) -> Result<bossy::Result<()>, Stopped>;
+}
+
+impl CommandExt for bossy::Command {
+    fn run_and_wait_in(&mut self, mode: OutputMode) -> bossy::Result<()> {
+        match mode {
+            OutputMode::Capture => self.run_and_wait_for_output().map(|_| ()),
+            OutputMode::Inherit => self.run_and_wait().map(|_| ()),
+        }
+    }
+
+    fn run_and_wait_within(
+        &mut self,
+        mode: OutputMode,
+        mut progress: Progress<'_>,
+        limits: &Limits,
+    ) -> Result<bossy::Result<()>, Stopped> {
+        let mut attempt = 1;
+        loop {
+            let (result, stderr) = run_once(self, mode, reborrow(&mut progress), limits)?;
+            let backoff = match retry_after(result, stderr, attempt, limits.retry) {
+                Ok(backoff) => backoff,
+                Err(done) => return done,
+            };
+            limits.sleep(backoff)?;
+            attempt += 1;
+        }
+    }
+}
+
+/// Decides what to do after `attempt` finished with `result`: either wait
+/// for the returned backoff and try again, or return the error.
+fn retry_after(
+    result: bossy::Result<()>,
+    stderr: String,
+    attempt: u32,
+    retry: RetryPolicy,
+) -> Result<Duration, Result<bossy::Result<()>, Stopped>> {
+    let err = match result {
+        Ok(()) => return Err(Ok(Ok(()))),
+        Err(err) => err,
+    };
+    let stderr = match err.stderr() {
+        Some(captured) if stderr.is_empty() => String::from_utf8_lossy(captured).into_owned(),
+        _ => stderr,
+    };
+    if looks_like_prompt(&stderr) {
+        return Err(Err(Stopped::AuthenticationRequired(err)));
+    }
+    if !looks_transient(&stderr) {
+        return Err(Ok(Err(err)));
+    }
+    if attempt >= retry.attempts {
+        return Err(if attempt > 1 {
+            Err(Stopped::GaveUp {
+                attempts: attempt,
+                source: err,
+            })
+        } else {
+            Ok(Err(err))
+        });
+    }
+    let backoff = retry.backoff_for(attempt);
+    log::warn!(
+        "attempt {} of {} failed, so retrying in {:?}: {}",
+        attempt,
+        retry.attempts,
+        backoff,
+        err
+    );
+    Ok(backoff)
+}
+
+/// Runs `command` once, returning what it wrote to stderr that wasn't
+/// progress if that isn't already in the error.
+fn run_once(
+    command: &mut bossy::Command,
+    mode: OutputMode,
+    mut progress: Progress<'_>,
+    limits: &Limits,
+) -> Result<(bossy::Result<()>, String), Stopped> {
+    if limits.cancelled() {
+        return Err(Stopped::Cancelled);
+    }
+    if progress.is_none() && limits.is_empty() {
+        return Ok((command.run_and_wait_in(mode), String::new()));
+    }
+    command.set_stderr_piped();
+    if mode == OutputMode::Capture {
+        // Nothing reads stdout until git exits, so it can't be piped
+        // without risking a deadlock.
+        command.set_stdout_null();
+    }
+    let start = Instant::now();
+    let mut handle = match command.run() {
+        Ok(handle) => handle,
+        Err(err) => return Ok((Err(err), String::new())),
+    };
+    let mut lines = Lines::new(mode);
+    match handle.stderr() {
+        Some(stderr) if limits.is_empty() => read_chunks(stderr, |chunk| {
+            lines.push(chunk, &mut progress);
+            true
+        }),
+        // We need to keep hold of the handle to kill the command, so
+        // stderr gets read through a copy of it on another thread.
+        Some(stderr) => match try_clone(stderr) {
+            Ok(mut stderr) => {
+                let (tx, rx) = mpsc::channel();
+                std::thread::spawn(move || {
+                    read_chunks(&mut stderr, |chunk| tx.send(chunk.to_vec()).is_ok())
+                });
+                loop {
+                    // This is checked before every read, since a command
+                    // that keeps writing progress would otherwise never be
+                    // stopped.
+                    if let Some(stopped) = limits.stopped(start) {
+                        if let Err(err) = handle.kill() {
+                            log::error!("failed to kill stopped command: {}", err);
+                        }
+                        let _ = handle.wait();
+                        return Err(stopped);
+                    }
+                    match rx.recv_timeout(limits.wait(start)) {
+                        Ok(chunk) => lines.push(&chunk, &mut progress),
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                        Err(mpsc::RecvTimeoutError::Timeout) => (),
+                    }
+                }
+            }
+            Err(err) => {
+                log::warn!(
+                    "failed to watch command for a timeout or cancellation: {}",
+                    err
+                );
+                read_chunks(stderr, |chunk| {
+                    lines.push(chunk, &mut progress);
+                    true
+                })
+            }
+        },
+        None => (),
+    }
+    let stderr = lines.finish(&mut progress);
+    Ok((handle.wait().map(|_| ()), stderr))
+}
+
+/// The same as [`CommandExt::run_and_wait_within`], but waits for `command`
+/// without blocking the async runtime, and kills it if the future is dropped.
+/// There's no progress hook, so git's stderr is only logged.
+///
+/// On platforms other than Unix, where tokio can't read the pipe git writes
+/// its stderr to, the command is waited for on the blocking pool instead.
+#[cfg(feature = "tokio")]
+pub(crate) async fn run_and_wait_within_async(
+    command: &mut bossy::Command,
+    mode: OutputMode,
+    limits: &Limits,
+) -> Result<bossy::Result<()>, Stopped> {
+    let mut attempt = 1;
+    loop {
+        let (result, stderr) = run_once_async(command, mode, limits).await?;
+        let backoff = match retry_after(result, stderr, attempt, limits.retry) {
+            Ok(backoff) => backoff,
+            Err(done) => return done,
+        };
+        limits.sleep_async(backoff).await?;
+        attempt += 1;
+    }
+}
+
+/// Kills the command if it's dropped before being waited on, which is what
+/// happens when the runtime is shut down underneath us.
+#[cfg(feature = "tokio")]
+struct KillOnDrop(Option<bossy::Handle>);
+
+#[cfg(feature = "tokio")]
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let Some(mut handle) = self.0.take() {
+            if let Err(err) = handle.kill() {
+                log::error!("failed to kill stopped command: {}", err);
+            }
+            let _ = handle.wait();
+        }
+    }
+}
+
+/// git's stderr, read without blocking the async runtime.
+#[cfg(feature = "tokio")]
+enum AsyncStderr {
+    #[cfg(unix)]
+    Pipe(tokio::net::unix::pipe::Receiver),
+    /// tokio can only read pipes on Unix, so elsewhere, a thread of our own
+    /// reads it for us.
+    #[cfg(not(unix))]
+    Thread(tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>),
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncStderr {
+    fn new(stderr: &bossy::ChildStderr) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsFd as _;
+            stderr
+                .as_fd()
+                .try_clone_to_owned()
+                .and_then(tokio::net::unix::pipe::Receiver::from_owned_fd)
+                .map(Self::Pipe)
+        }
+        #[cfg(not(unix))]
+        {
+            let mut stderr = try_clone(stderr)?;
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            std::thread::spawn(move || {
+                read_chunks(&mut stderr, |chunk| tx.send(chunk.to_vec()).is_ok())
+            });
+            Ok(Self::Thread(rx))
+        }
+    }
+
+    /// The next chunk git writes, or `None` once it closes stderr.
+    async fn next(&mut self) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(unix)]
+            Self::Pipe(pipe) => {
+                use tokio::io::AsyncReadExt as _;
+                let mut buf = [0; 4096];
+                loop {
+                    match pipe.read(&mut buf).await {
+                        Ok(0) => return None,
+                        Ok(n) => return Some(buf[..n].to_vec()),
+                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+                        Err(err) => {
+                            log::warn!("failed to read output from git: {}", err);
+                            return None;
+                        }
+                    }
+                }
+            }
+            #[cfg(not(unix))]
+            Self::Thread(rx) => rx.recv().await,
+        }
+    }
+}
+
+/// The same as [`run_once`], but without blocking the async runtime.
+#[cfg(feature = "tokio")]
+async fn run_once_async(
+    command: &mut bossy::Command,
+    mode: OutputMode,
+    limits: &Limits,
+) -> Result<(bossy::Result<()>, String), Stopped> {
+    if limits.cancelled() {
+        return Err(Stopped::Cancelled);
+    }
+    command.set_stderr_piped();
+    if mode == OutputMode::Capture {
+        command.set_stdout_null();
+    }
+    let start = Instant::now();
+    let mut child = match command.run() {
+        Ok(handle) => KillOnDrop(Some(handle)),
+        Err(err) => return Ok((Err(err), String::new())),
+    };
+    let mut lines = Lines::new(mode);
+    let stderr = child
+        .0
+        .as_mut()
+        .and_then(bossy::Handle::stderr)
+        .map(|stderr| AsyncStderr::new(stderr));
+    match stderr {
+        Some(Ok(mut stderr)) => loop {
+            if let Some(stopped) = limits.stopped(start) {
+                // Dropping `child` kills it.
+                return Err(stopped);
+            }
+            match tokio::time::timeout(limits.wait(start), stderr.next()).await {
+                Ok(Some(chunk)) => lines.push(&chunk, &mut None),
+                Ok(None) => break,
+                Err(_) => (),
+            }
+        },
+        Some(Err(err)) => {
+            log::warn!(
+                "failed to watch command for a timeout or cancellation: {}",
+                err
+            );
+            let mut handle = child.0.take().expect("developer error: `child` vacant");
+            return Ok(blocking(move || {
+                if let Some(stderr) = handle.stderr() {
+                    read_chunks(stderr, |chunk| {
+                        lines.push(chunk, &mut None);
+                        true
+                    });
+                }
+                (handle.wait().map(|_| ()), lines.finish(&mut None))
+            })
+            .await);
+        }
+        None => (),
+    }
+    let stderr = lines.finish(&mut None);
+    // git has closed stderr, so it's exiting, and this won't block for long.
+    let handle = child.0.take().expect("developer error: `child` vacant");
+    let result = blocking(move || handle.wait().map(|_| ())).await;
+    Ok((result, stderr))
+}
+
+fn read_chunks(reader: &mut impl Read, mut f: impl FnMut(&[u8]) -> bool) {
+    let mut buf = [0; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if !f(&buf[..n]) {
+                    break;
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(err) => {
+                log::warn!("failed to read output from git: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn try_clone(stderr: &bossy::ChildStderr) -> std::io::Result<std::fs::File> {
+    use std::os::unix::io::AsFd as _;
+    stderr.as_fd().try_clone_to_owned().map(Into::into)
+}
+
+#[cfg(windows)]
+fn try_clone(stderr: &bossy::ChildStderr) -> std::io::Result<std::fs::File> {
+    use std::os::windows::io::AsHandle as _;
+    stderr.as_handle().try_clone_to_owned().map(Into::into)
+}
+
+/// Splits git's stderr into lines, sending progress to the progress hook and
+/// everything else wherever `mode` says. The lines that aren't progress are
+/// also kept, since they won't end up in the error if the command fails.
+struct Lines {
+    mode: OutputMode,
+    pending: Vec<u8>,
+    other: String,
+}
+
+impl Lines {
+    fn new(mode: OutputMode) -> Self {
+        Self {
+            mode,
+            pending: Vec::new(),
+            other: String::new(),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8], progress: &mut Progress<'_>) {
+        self.pending.extend_from_slice(chunk);
+        // git redraws progress lines in place by ending them with `\r` until
+        // they're done.
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\r' || b == b'\n') {
+            let line = self.pending.drain(..=end).collect::<Vec<_>>();
+            self.line(&line[..end], progress);
+        }
+    }
+
+    fn finish(mut self, progress: &mut Progress<'_>) -> String {
+        let line = std::mem::take(&mut self.pending);
+        self.line(&line, progress);
+        self.other
+    }
+
+    fn line(&mut self, line: &[u8], progress: &mut Progress<'_>) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+        match (ProgressEvent::parse(line), progress) {
+            (Some(event), Some(progress)) => return progress(event),
+            _ if self.mode == OutputMode::Inherit => eprintln!("{}", line),
+            _ => log::info!("git: {}", line),
+        }
+        self.other.push_str(line);
+        self.other.push('\n');
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A command that writes progress to stderr forever, like a clone from a
+    /// server that trickles data.
+    fn chatty() -> bossy::Command {
+        bossy::Command::impure("sh").with_args([
+            "-c",
+            "while :; do echo 'Receiving objects:   1% (1/100)' >&2; sleep 0.01; done",
+        ])
+    }
+
+    #[test]
+    fn chatty_command_still_times_out() {
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut events = 0;
+        let mut progress = |_| events += 1;
+        let result =
+            chatty().run_and_wait_within(OutputMode::Capture, Some(&mut progress), &limits);
+        assert!(matches!(result, Err(Stopped::TimedOut(_))), "{:?}", result);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(events > 0);
+    }
+
+    #[test]
+    fn chatty_command_can_be_cancelled() {
+        let token = CancellationToken::new();
+        let limits = Limits {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            token.cancel();
+        });
+        let result = chatty().run_and_wait_within(OutputMode::Capture, None, &limits);
+        canceller.join().unwrap();
+        assert!(matches!(result, Err(Stopped::Cancelled)), "{:?}", result);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "tokio")]
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_command_times_out_without_blocking_the_runtime() {
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let ticks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let result = runtime().block_on({
+            let ticks = Arc::clone(&ticks);
+            async move {
+                // With only one thread, this only gets to run while the
+                // command is being waited for if that doesn't block.
+                tokio::spawn(async move {
+                    loop {
+                        ticks.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                });
+                run_and_wait_within_async(&mut chatty(), OutputMode::Capture, &limits).await
+            }
+        });
+        assert!(matches!(result, Err(Stopped::TimedOut(_))), "{:?}", result);
+        assert!(ticks.load(Ordering::SeqCst) > 5);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn dropping_an_async_command_kills_it() {
+        let dir = crate::fixtures::TempDir::new().unwrap();
+        let pid_file = dir.path().join("pid");
+        let mut command = bossy::Command::impure("sh").with_args([
+            "-c",
+            "echo $$ > \"$0\"; while :; do sleep 0.01; done",
+            pid_file.to_str().unwrap(),
+        ]);
+        let limits = Limits::default();
+        let result = runtime().block_on(async {
+            tokio::time::timeout(
+                Duration::from_millis(300),
+                run_and_wait_within_async(&mut command, OutputMode::Capture, &limits),
+            )
+            .await
+        });
+        assert!(result.is_err());
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let alive = bossy::Command::impure("kill")
+            .with_args(["-0", pid.trim()])
+            .run_and_wait_for_output()
+            .is_ok();
+        assert!(!alive);
+    }
+}
@@ -0,0 +1,39 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to get config value {key:?}: {source}")]
+    GetFailed { key: String, source: bossy::Error },
+    #[error("Config value {key:?} was {value:?}, which isn't a valid {kind}.")]
+    ParseFailed {
+        key: String,
+        value: String,
+        kind: &'static str,
+    },
+    #[error("Failed to set config value {key:?}: {source}")]
+    SetFailed { key: String, source: bossy::Error },
+    #[error("Failed to unset config value {key:?}: {source}")]
+    UnsetFailed { key: String, source: bossy::Error },
+}
+
+/// Which config file to read from or write to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// The repo's own config, i.e. `.git/config`.
+    #[default]
+    Local,
+    /// The user's config, i.e. `~/.gitconfig`.
+    Global,
+    /// The repo's `.gitmodules`.
+    Modules,
+}
+
+impl Scope {
+    pub(crate) fn args(self) -> &'static [&'static str] {
+        match self {
+            Self::Local => &["--local"],
+            Self::Global => &["--global"],
+            Self::Modules => &["--file", ".gitmodules"],
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{config, repo, submodule, version};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display},
+    path::PathBuf,
+    process::ExitStatus,
+};
+use thiserror::Error;
+
+/// Any error this crate returns, for when you'd rather not keep track of
+/// which module it came from. The module errors are still there to match on.
+///
+/// This displays the same as the error it wraps, followed by the
+/// [`FailedCommand`] behind it, if there is one.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{}", describe(.0))]
+    Repo(#[from] repo::Error),
+    #[error("{}", describe(.0))]
+    Submodule(#[from] submodule::Error),
+    #[error("{}", describe(.0))]
+    Config(#[from] config::Error),
+    #[error("{}", describe(.0))]
+    Version(#[from] version::Error),
+    /// From the [`crate::Git`] methods that run a command directly.
+    #[error("{}", describe(.0))]
+    Command(#[from] bossy::Error),
+    /// From the [`crate::Git`] methods that read files directly.
+    #[error("{}", describe(.0))]
+    Io(#[from] std::io::Error),
+}
+
+/// The errors behind `err`, starting with `err` itself.
+fn chain<'a>(
+    err: &'a (dyn StdError + 'static),
+) -> impl Iterator<Item = &'a (dyn StdError + 'static)> {
+    std::iter::successors(Some(err), |&err| err.source())
+}
+
+fn failed_command(err: &(dyn StdError + 'static)) -> Option<FailedCommand> {
+    chain(err).find_map(|err| {
+        if let Some(err) = err.downcast_ref::<bossy::Error>() {
+            Some(FailedCommand::parse(err.command(), err.status()))
+        } else if let Some(
+            repo::Error::TimedOut { command, .. } | repo::Error::Cancelled { command },
+        ) = err.downcast_ref::<repo::Error>()
+        {
+            // These don't have an underlying error, since the command was
+            // killed rather than failing.
+            Some(FailedCommand::parse(command, None))
+        } else {
+            None
+        }
+    })
+}
+
+fn describe(err: &(dyn StdError + 'static)) -> String {
+    // bossy's messages end with stderr, which usually ends with a newline.
+    let message = err.to_string();
+    let message = message.trim_end();
+    match failed_command(err) {
+        Some(command) => format!("{} ({})", message, command),
+        None => message.to_owned(),
+    }
+}
+
+impl Error {
+    fn inner(&self) -> &(dyn StdError + 'static) {
+        match self {
+            Self::Repo(err) => err,
+            Self::Submodule(err) => err,
+            Self::Config(err) => err,
+            Self::Version(err) => err,
+            Self::Command(err) => err,
+            Self::Io(err) => err,
+        }
+    }
+
+    /// The git command that failed, if one did.
+    pub fn failed_command(&self) -> Option<FailedCommand> {
+        failed_command(self.inner())
+    }
+
+    /// The git command that failed, as written in error messages.
+    pub fn command(&self) -> Option<String> {
+        self.failed_command().map(|command| command.command_line())
+    }
+
+    /// How the failed git command exited, if it got as far as exiting on its
+    /// own.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.failed_command().and_then(|command| command.exit)
+    }
+}
+
+/// A git command that failed, for reproducing the failure by hand.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedCommand {
+    pub program: String,
+    /// The arguments, not counting the `-C <cwd>` and `--no-pager` we pass to
+    /// every command. bossy only keeps the command line (with the arguments
+    /// joined by spaces), so an argument with a space in it comes out as
+    /// several.
+    pub args: Vec<String>,
+    /// The directory git ran in, or `None` if the command wasn't run through
+    /// [`crate::Git`].
+    pub cwd: Option<PathBuf>,
+    /// `None` if git never exited on its own, like if it couldn't be started
+    /// or was killed.
+    pub exit: Option<ExitStatus>,
+}
+
+impl Display for FailedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "command: {}", self.command_line())?;
+        if let Some(cwd) = &self.cwd {
+            write!(f, ", cwd: {}", cwd.display())?;
+        }
+        match self.exit.map(|exit| (exit, exit.code())) {
+            Some((_, Some(code))) => write!(f, ", exit: {}", code),
+            Some((exit, None)) => write!(f, ", exit: {}", exit),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FailedCommand {
+    /// Picks apart a command line from [`crate::Git::command`], which starts
+    /// with "<program> -C <cwd> --no-pager".
+    fn parse(command: &str, exit: Option<ExitStatus>) -> Self {
+        let (program, cwd, rest) = match command.split_once(" -C ").and_then(|(program, rest)| {
+            rest.split_once(" --no-pager")
+                .map(|(cwd, rest)| (program, Some(PathBuf::from(cwd)), rest))
+        }) {
+            Some(parts) => parts,
+            None => {
+                let (program, rest) = command.split_once(' ').unwrap_or((command, ""));
+                (program, None, rest)
+            }
+        };
+        Self {
+            program: program.to_owned(),
+            args: rest.split_whitespace().map(ToOwned::to_owned).collect(),
+            cwd,
+            exit,
+        }
+    }
+
+    /// The program followed by its arguments.
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.program.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
@@ -0,0 +1,336 @@
+//! Throwaway repos for testing code that uses this crate, built by running
+//! git the same way the rest of the crate does.
+
+use crate::{submodule::Submodule, Error, Git};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Config set in every fixture, so that commits work the same regardless of
+/// the user's own config.
+const FIXTURE_CONFIG: &[(&str, &str)] = &[
+    ("user.name", "hit"),
+    ("user.email", "hit@example.com"),
+    ("commit.gpgSign", "false"),
+    ("tag.gpgSign", "false"),
+];
+
+/// The branch fixtures start out on, whatever `init.defaultBranch` says.
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// A directory under the system temp directory that's removed on drop.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.path) {
+            log::error!("failed to remove fixture dir at {:?}: {}", self.path, err);
+        }
+    }
+}
+
+impl TempDir {
+    pub fn new() -> std::io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "hit-fixture-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A repo with a working tree, for committing to and cloning from.
+#[derive(Clone, Debug)]
+pub struct FixtureRepo {
+    path: PathBuf,
+}
+
+impl FixtureRepo {
+    /// Creates an empty repo at `path`, which can be an existing empty
+    /// directory. It's on [`DEFAULT_BRANCH`], with no commits yet.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        // Clones record our path as their remote URL, which has to keep
+        // working from wherever they are. This isn't canonicalized, since git
+        // on Windows can't take the verbatim paths that gives.
+        let path = std::path::absolute(path)?;
+        let fixture = Self { path };
+        fixture.run(&["init", "-q"])?;
+        fixture.run(&[
+            "symbolic-ref",
+            "HEAD",
+            &format!("refs/heads/{}", DEFAULT_BRANCH),
+        ])?;
+        for (key, value) in FIXTURE_CONFIG {
+            fixture.run(&["config", key, value])?;
+        }
+        Ok(fixture)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// What to pass to [`crate::repo::Repo::update`] to clone this repo.
+    pub fn url(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+
+    pub fn git(&self) -> Git<'_> {
+        Git::new(&self.path)
+    }
+
+    fn run(&self, args: &[&str]) -> bossy::Result<()> {
+        self.git().command().with_args(args).run_and_wait()?;
+        Ok(())
+    }
+
+    fn head(&self) -> bossy::Result<String> {
+        self.git()
+            .command()
+            .with_args(["rev-parse", "HEAD"])
+            .run_and_wait_for_str(|hash| hash.trim_end().to_owned())
+    }
+
+    /// Writes `contents` to `path` (relative to the repo, creating any
+    /// missing directories) and commits just that file, returning the hash
+    /// of the new commit.
+    pub fn commit_file(
+        &self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+        message: &str,
+    ) -> Result<String, Error> {
+        let path = path.as_ref();
+        let full_path = self.path.join(path);
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&full_path, contents)?;
+        self.git()
+            .command()
+            .with_args(["add", "--"])
+            .with_arg(path)
+            .run_and_wait()?;
+        self.run(&["commit", "-q", "-m", message])?;
+        Ok(self.head()?)
+    }
+
+    /// Tags `HEAD` as `name`, with an annotated tag so that it's seen by
+    /// `git describe`.
+    pub fn tag(&self, name: &str) -> Result<(), Error> {
+        self.run(&["tag", "-a", "-m", name, name])?;
+        Ok(())
+    }
+
+    /// Creates branch `name` at `HEAD` and switches to it, so that later
+    /// commits go there.
+    pub fn branch(&self, name: &str) -> Result<(), Error> {
+        self.run(&["checkout", "-q", "-b", name])?;
+        Ok(())
+    }
+
+    /// Switches to the existing branch `name`.
+    pub fn checkout(&self, name: &str) -> Result<(), Error> {
+        self.run(&["checkout", "-q", name])?;
+        Ok(())
+    }
+
+    /// Adds `submodule` and commits it, returning the hash of the new commit.
+    /// It's registered under the same name [`Submodule::init`] would use, so
+    /// that clones of this repo see it as already added.
+    ///
+    /// git refuses to clone submodules from local paths unless
+    /// `protocol.file.allow` is `always`, which this sets for the `add`
+    /// itself. Anything initializing the submodule in a clone of this repo
+    /// needs the same, like through [`crate::repo::Repo::with_env`] with
+    /// `GIT_CONFIG_PARAMETERS`.
+    pub fn add_submodule(&self, submodule: &Submodule, message: &str) -> Result<String, Error> {
+        let mut command = self.git().command();
+        command.add_args(["-c", "protocol.file.allow=always", "submodule", "add", "-q"]);
+        if let Some(name) = submodule.name() {
+            command.add_arg("--name").add_arg(name);
+        }
+        if let Some(branch) = submodule.branch() {
+            command.add_arg("-b").add_arg(branch);
+        }
+        command
+            .with_arg(submodule.remote())
+            .with_arg(submodule.path())
+            .run_and_wait()?;
+        self.run(&["commit", "-q", "-m", message])?;
+        Ok(self.head()?)
+    }
+
+    /// Pushes every branch and tag to a bare repo at `remote_dir`, creating it
+    /// if it doesn't exist yet, and returns its path (usable as a URL). The
+    /// bare repo's `HEAD` is pointed at our current branch, so that's what
+    /// clones of it check out.
+    pub fn push_to_bare(&self, remote_dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        let remote_dir = remote_dir.as_ref();
+        if !remote_dir.exists() {
+            std::fs::create_dir_all(remote_dir)?;
+            Git::new(remote_dir)
+                .command()
+                .with_args(["init", "-q", "--bare"])
+                .run_and_wait()?;
+        }
+        let remote_dir = std::path::absolute(remote_dir)?;
+        for refs in ["--all", "--tags"] {
+            self.git()
+                .command()
+                .with_args(["push", "-q", "--force"])
+                .with_arg(&remote_dir)
+                .with_arg(refs)
+                .run_and_wait()?;
+        }
+        let branch = self
+            .git()
+            .command()
+            .with_args(["symbolic-ref", "HEAD"])
+            .run_and_wait_for_str(|branch| branch.trim_end().to_owned())?;
+        Git::new(&remote_dir)
+            .command()
+            .with_args(["symbolic-ref", "HEAD"])
+            .with_arg(branch)
+            .run_and_wait()?;
+        Ok(remote_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::Repo;
+
+    #[test]
+    fn temp_dir_is_removed_on_drop() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_owned();
+        assert!(path.is_dir());
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn new_repo_is_empty_and_on_default_branch() {
+        let dir = TempDir::new().unwrap();
+        let fixture = FixtureRepo::new(dir.path().join("repo")).unwrap();
+        assert!(fixture.path().is_absolute());
+        assert!(fixture.head().is_err());
+        let branch = fixture
+            .git()
+            .command_parse("symbolic-ref --short HEAD")
+            .run_and_wait_for_str(|branch| branch.trim_end().to_owned())
+            .unwrap();
+        assert_eq!(branch, DEFAULT_BRANCH);
+    }
+
+    #[test]
+    fn commit_file_creates_dirs_and_returns_head() {
+        let dir = TempDir::new().unwrap();
+        let fixture = FixtureRepo::new(dir.path().join("repo")).unwrap();
+        let hash = fixture
+            .commit_file("a/b/c.txt", "contents", "add c")
+            .unwrap();
+        assert_eq!(hash, fixture.head().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(fixture.path().join("a/b/c.txt")).unwrap(),
+            "contents"
+        );
+        let repo = Repo::from_path(fixture.path());
+        assert!(!repo.is_dirty().unwrap());
+        assert_eq!(repo.latest_subject().unwrap(), "add c");
+    }
+
+    #[test]
+    fn clones_see_commits_tags_and_branches() {
+        let dir = TempDir::new().unwrap();
+        let fixture = FixtureRepo::new(dir.path().join("upstream")).unwrap();
+        let first = fixture.commit_file("file", "1", "first").unwrap();
+        fixture.tag("v1").unwrap();
+        fixture.branch("feature").unwrap();
+        let feature = fixture.commit_file("file", "2", "on feature").unwrap();
+        fixture.checkout(DEFAULT_BRANCH).unwrap();
+
+        let clone = Repo::from_path(dir.path().join("clone"));
+        let outcome = clone.update(fixture.url()).unwrap();
+        assert!(outcome.freshly_cloned);
+        assert_eq!(outcome.new_head, first);
+        assert_eq!(
+            std::fs::read_to_string(clone.path().join("file")).unwrap(),
+            "1"
+        );
+        let described = clone
+            .git()
+            .command_parse("describe")
+            .run_and_wait_for_str(|tag| tag.trim_end().to_owned())
+            .unwrap();
+        assert_eq!(described, "v1");
+
+        let second = fixture.commit_file("file", "3", "second").unwrap();
+        let outcome = clone.update(fixture.url()).unwrap();
+        assert!(!outcome.freshly_cloned);
+        assert_eq!(outcome.old_head.as_deref(), Some(first.as_str()));
+        assert_eq!(outcome.new_head, second);
+
+        let other = Repo::from_path(dir.path().join("feature-clone"));
+        let outcome = other.update_branch(fixture.url(), Some("feature")).unwrap();
+        assert_eq!(outcome.new_head, feature);
+    }
+
+    #[test]
+    fn push_to_bare_can_be_cloned_from() {
+        let dir = TempDir::new().unwrap();
+        let fixture = FixtureRepo::new(dir.path().join("upstream")).unwrap();
+        fixture.commit_file("file", "1", "first").unwrap();
+        fixture.tag("v1").unwrap();
+        fixture.branch("release").unwrap();
+        let head = fixture.commit_file("file", "2", "release").unwrap();
+        let bare = fixture.push_to_bare(dir.path().join("remote.git")).unwrap();
+        assert!(Repo::is_repo(&bare));
+
+        // The bare repo's `HEAD` follows whatever we had checked out.
+        let clone = Repo::from_path(dir.path().join("clone"));
+        assert_eq!(clone.update(&bare).unwrap().new_head, head);
+        assert_eq!(clone.current_branch().unwrap().as_deref(), Some("release"));
+
+        // Pushing again updates the existing bare repo.
+        let newer = fixture.commit_file("file", "3", "newer").unwrap();
+        assert_eq!(
+            fixture.push_to_bare(dir.path().join("remote.git")).unwrap(),
+            bare
+        );
+        assert_eq!(clone.update(&bare).unwrap().new_head, newer);
+    }
+
+    #[test]
+    fn add_submodule_registers_it_under_its_name() {
+        let dir = TempDir::new().unwrap();
+        let dep = FixtureRepo::new(dir.path().join("dep")).unwrap();
+        dep.commit_file("lib", "lib", "lib").unwrap();
+        let fixture = FixtureRepo::new(dir.path().join("superproject")).unwrap();
+        fixture.commit_file("README", "hi", "readme").unwrap();
+        let submodule = Submodule::with_remote_and_path(dep.path().to_str().unwrap(), "deps/dep");
+        let hash = fixture.add_submodule(&submodule, "add dep").unwrap();
+        assert_eq!(hash, fixture.head().unwrap());
+        assert!(fixture.path().join("deps/dep/lib").is_file());
+        let submodules = fixture.git().submodules().unwrap();
+        assert_eq!(submodules.len(), 1);
+        assert_eq!(submodules[0].name(), submodule.name());
+        assert_eq!(submodules[0].path(), Path::new("deps/dep"));
+    }
+}
@@ -1,26 +1,291 @@
+#![allow(clippy::result_large_err)]
+
+mod auth;
+pub mod backend;
+mod command;
+pub mod config;
+mod error;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixtures;
+mod lock;
+#[cfg(feature = "gix")]
+mod native;
+pub mod progress;
 pub mod repo;
 pub mod submodule;
+pub mod version;
+pub mod workspace;
+
+#[cfg(feature = "tokio")]
+pub(crate) use self::command::{blocking, run_and_wait_within_async, spawn_cancellable};
+pub(crate) use self::command::{reborrow, CommandExt, Limits, Progress, Stopped};
+pub use self::{
+    auth::{Auth, Credentials},
+    backend::GitBackend,
+    command::{CancellationToken, RetryPolicy},
+    error::{Error, FailedCommand},
+    lock::LockPolicy,
+    version::{ensure_git, git_version, GitVersion},
+};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Whether `err` is git complaining that it wasn't run in a repo.
+pub(crate) fn not_a_repo(err: &bossy::Error) -> bool {
+    err.stderr()
+        .map(String::from_utf8_lossy)
+        .filter(|stderr| stderr.contains("not a git repository"))
+        .is_some()
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt as _;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt as _;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_to_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        std::borrow::Cow::Borrowed(s) => s.as_bytes().into(),
+        std::borrow::Cow::Owned(s) => s.into_bytes().into(),
+    }
+}
+
+/// What to do with the stdout and stderr of commands that change things,
+/// like clones, fetches, and checkouts. Queries always capture their output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Pipe the output, so nothing reaches the terminal. Failed commands still
+    /// include their output in the returned error.
+    #[default]
+    Capture,
+    /// Let the output go straight to our own stdout and stderr.
+    Inherit,
+}
 
-use std::path::Path;
+/// Config that changes the output we parse, set back to git's defaults for
+/// every command. Settings passed in `GIT_CONFIG_PARAMETERS` beat any config
+/// file.
+const STABLE_CONFIG: &[&str] = &[
+    "color.ui=false",
+    "column.ui=never",
+    "core.quotePath=true",
+    "diff.mnemonicPrefix=false",
+    "diff.noprefix=false",
+    "log.abbrevCommit=false",
+    "log.decorate=false",
+    "log.showSignature=false",
+    "status.branch=false",
+    "status.short=false",
+    "status.showStash=false",
+];
 
 #[derive(Clone, Copy, Debug)]
 pub struct Git<'a> {
     root: &'a Path,
+    program: &'a Path,
+    backend: Option<&'a Arc<dyn GitBackend>>,
+    output: OutputMode,
+    retry: RetryPolicy,
+    interactive: bool,
+    env: &'a [(OsString, OsString)],
+    auth: Option<&'a Auth>,
+    cancel: Option<&'a CancellationToken>,
 }
 
 impl<'a> Git<'a> {
     pub fn new(root: &'a Path) -> Self {
-        Self { root }
+        Self {
+            root,
+            program: Path::new("git"),
+            backend: None,
+            output: Default::default(),
+            retry: Default::default(),
+            interactive: false,
+            env: &[],
+            auth: None,
+            cancel: None,
+        }
+    }
+
+    /// The same settings, but rooted somewhere else.
+    pub(crate) fn at<'b>(&self, root: &'b Path) -> Git<'b>
+    where
+        'a: 'b,
+    {
+        Git {
+            root,
+            program: self.program,
+            backend: self.backend,
+            output: self.output,
+            retry: self.retry,
+            interactive: self.interactive,
+            env: self.env,
+            auth: self.auth,
+            cancel: self.cancel,
+        }
+    }
+
+    /// The git executable to run, in place of whichever `git` is on `PATH`.
+    pub fn with_program(mut self, program: &'a Path) -> Self {
+        self.program = program;
+        self
+    }
+
+    /// Start commands from `backend` instead of running git directly. Version
+    /// checks still run [`Git::program`].
+    pub fn with_backend(mut self, backend: Option<&'a Arc<dyn GitBackend>>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_output_mode(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Used by [`submodule::Submodule::init`] when cloning submodules.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Let git prompt for credentials (and SSH host key confirmation) on the
+    /// terminal. By default, it's told not to, so that commands needing
+    /// credentials fail right away rather than waiting forever for input.
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Environment variables to set for every command, on top of our own.
+    /// These win over anything set by default, so passing `GIT_SSH_COMMAND`
+    /// here replaces the batch mode SSH command used when not interactive.
+    pub fn with_env(mut self, vars: &'a [(OsString, OsString)]) -> Self {
+        self.env = vars;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Option<&'a Auth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Lets `token` stop the clones done by [`submodule::Submodule::init`].
+    pub(crate) fn with_cancellation(mut self, token: Option<&'a CancellationToken>) -> Self {
+        self.cancel = token;
+        self
     }
 
     pub fn root(&'a self) -> &'a Path {
         self.root
     }
 
+    pub fn program(&self) -> &'a Path {
+        self.program
+    }
+
+    pub fn backend(&self) -> Option<&'a Arc<dyn GitBackend>> {
+        self.backend
+    }
+
+    /// The version of [`Git::program`]; see [`git_version`].
+    pub fn version(&self) -> Result<GitVersion, version::Error> {
+        version::version_of(self.program)
+    }
+
+    /// Like [`ensure_git`], but for [`Git::program`].
+    pub fn ensure_version(&self, required: GitVersion) -> Result<GitVersion, version::Error> {
+        version::ensure_version_of(self.program, required)
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        self.output
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn env(&self) -> &'a [(OsString, OsString)] {
+        self.env
+    }
+
+    pub fn auth(&self) -> Option<&'a Auth> {
+        self.auth
+    }
+
+    pub(crate) fn cancellation(&self) -> Option<&'a CancellationToken> {
+        self.cancel
+    }
+
+    /// `key` as the commands we run will see it.
+    fn var(&self, key: &str) -> Option<OsString> {
+        self.env
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var_os(key))
+    }
+
     pub fn command(&self) -> bossy::Command {
-        bossy::Command::impure("git")
-            .with_arg("-C")
-            .with_arg(self.root)
+        let mut command = match self.backend {
+            Some(backend) => backend.command(self.root),
+            None => bossy::Command::impure(self.program)
+                .with_arg("-C")
+                .with_arg(self.root),
+        };
+        command
+            .add_arg("--no-pager")
+            // We parse git's output (and sometimes its errors), so we don't
+            // let the locale or the user's config change it.
+            .add_env_var("LC_ALL", "C");
+        let mut parameters = self.var("GIT_CONFIG_PARAMETERS").unwrap_or_default();
+        for setting in STABLE_CONFIG {
+            if !parameters.is_empty() {
+                parameters.push(" ");
+            }
+            parameters.push(format!("'{}'", setting));
+        }
+        command.add_env_var("GIT_CONFIG_PARAMETERS", parameters);
+        if let Some(auth) = self.auth {
+            auth.apply(&mut command);
+        }
+        if !self.interactive {
+            command.add_env_var("GIT_TERMINAL_PROMPT", "0");
+            // This takes precedence over `core.sshCommand`, so we leave it be
+            // if it's set at all.
+            if self.var("GIT_SSH_COMMAND").is_none() && self.var("GIT_SSH").is_none() {
+                command.add_env_var("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+            }
+        }
+        for (key, value) in self.env {
+            // These were merged with ours above.
+            if key != "GIT_CONFIG_PARAMETERS" {
+                command.add_env_var(key, value);
+            }
+        }
+        command
     }
 
     pub fn command_parse(&self, arg_str: impl AsRef<str>) -> bossy::Command {
@@ -29,13 +294,65 @@ impl<'a> Git<'a> {
 
     pub fn init(&self) -> bossy::Result<()> {
         if !self.root.join(".git").exists() {
-            self.command().with_arg("init").run_and_wait()?;
+            self.command()
+                .with_arg("init")
+                .run_and_wait_in(self.output)?;
         }
         Ok(())
     }
 
+    /// `git rev-parse <flag>`, for a flag that prints a directory. git prints
+    /// some of these relative to where it's run, so we make them absolute.
+    fn rev_parse_dir(&self, flag: &str) -> Result<PathBuf, Error> {
+        let dir = self
+            .command()
+            .with_arg("rev-parse")
+            .with_arg(flag)
+            .run_and_wait_for_str(|s| self.root.join(s.trim_end_matches('\n')))?;
+        Ok(std::path::absolute(dir)?)
+    }
+
+    /// The repo's git directory, which is usually `.git`, but is somewhere
+    /// else in linked worktrees and submodules (where `.git` is a file
+    /// pointing to it), and is the repo itself if it's bare. This is where
+    /// `HEAD` and the index are kept.
+    pub fn git_dir(&self) -> Result<PathBuf, Error> {
+        self.rev_parse_dir("--git-dir")
+    }
+
+    /// The directory holding the repo's config, refs, and objects. This is the
+    /// same as [`Git::git_dir`], except in linked worktrees, which share all
+    /// of that with the main worktree.
+    pub fn common_dir(&self) -> Result<PathBuf, Error> {
+        self.rev_parse_dir("--git-common-dir")
+    }
+
+    /// Like `dir`, but `None` if there's no repo here.
+    fn dir_if_repo(
+        &self,
+        dir: fn(&Self) -> Result<PathBuf, Error>,
+    ) -> Result<Option<PathBuf>, Error> {
+        if !self.root.is_dir() {
+            return Ok(None);
+        }
+        match dir(self) {
+            Ok(dir) => Ok(Some(dir)),
+            Err(Error::Command(err)) if not_a_repo(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The contents of the repo's config file, or `None` if there's no repo
+    /// here (or it has no config). Finding the file means running git, since
+    /// it might not be in `.git`, so read this once rather than for every
+    /// lookup.
     pub fn config(&self) -> std::io::Result<Option<String>> {
-        let path = self.root.join(".git/config");
+        let path = match self.dir_if_repo(Self::common_dir) {
+            Ok(Some(common_dir)) => common_dir.join("config"),
+            Ok(None) => return Ok(None),
+            Err(Error::Io(err)) => return Err(err),
+            Err(err) => return Err(std::io::Error::other(err)),
+        };
         if path.exists() {
             std::fs::read_to_string(&path).map(Some)
         } else {
@@ -43,6 +360,153 @@ impl<'a> Git<'a> {
         }
     }
 
+    fn config_get_typed(
+        &self,
+        key: &str,
+        scope: config::Scope,
+        kind: Option<&str>,
+    ) -> Result<Option<String>, config::Error> {
+        let mut command = self.command();
+        command.add_arg("config").add_args(scope.args());
+        if let Some(kind) = kind {
+            command.add_arg(format!("--type={}", kind));
+        }
+        match command
+            .with_arg("--get")
+            .with_arg(key)
+            .run_and_wait_for_str(|s| s.trim_end_matches('\n').to_owned())
+        {
+            Ok(value) => Ok(Some(value)),
+            // `--get` exits with 1 if the key isn't set.
+            Err(err) if err.code() == Some(1) => Ok(None),
+            Err(source) => Err(config::Error::GetFailed {
+                key: key.to_owned(),
+                source,
+            }),
+        }
+    }
+
+    /// The value of `key` in `scope`, or `None` if it isn't set.
+    pub fn config_get(
+        &self,
+        key: &str,
+        scope: config::Scope,
+    ) -> Result<Option<String>, config::Error> {
+        self.config_get_typed(key, scope, None)
+    }
+
+    pub fn config_get_bool(
+        &self,
+        key: &str,
+        scope: config::Scope,
+    ) -> Result<Option<bool>, config::Error> {
+        self.config_get_typed(key, scope, Some("bool"))?
+            .map(|value| match value.as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(config::Error::ParseFailed {
+                    key: key.to_owned(),
+                    value,
+                    kind: "bool",
+                }),
+            })
+            .transpose()
+    }
+
+    /// git expands unit suffixes for us, so `1k` comes back as `1024`.
+    pub fn config_get_int(
+        &self,
+        key: &str,
+        scope: config::Scope,
+    ) -> Result<Option<i64>, config::Error> {
+        self.config_get_typed(key, scope, Some("int"))?
+            .map(|value| {
+                value.parse().map_err(|_| config::Error::ParseFailed {
+                    key: key.to_owned(),
+                    value,
+                    kind: "int",
+                })
+            })
+            .transpose()
+    }
+
+    /// Every key in `scope` matching the regex `pattern`, along with its
+    /// value, in the order they appear.
+    pub fn config_get_regexp(
+        &self,
+        pattern: &str,
+        scope: config::Scope,
+    ) -> Result<Vec<(String, String)>, config::Error> {
+        match self
+            .command()
+            .with_arg("config")
+            .with_args(scope.args())
+            .with_args(["-z", "--get-regexp"])
+            .with_arg(pattern)
+            .run_and_wait_for_str(|s| {
+                // With `-z`, each entry is "<key>\n<value>\0".
+                s.split_terminator('\0')
+                    .map(|entry| {
+                        let (key, value) = entry.split_once('\n').unwrap_or((entry, ""));
+                        (key.to_owned(), value.to_owned())
+                    })
+                    .collect()
+            }) {
+            Ok(entries) => Ok(entries),
+            // This also covers the config file not existing.
+            Err(err) if err.code() == Some(1) => Ok(Vec::new()),
+            Err(source) => Err(config::Error::GetFailed {
+                key: pattern.to_owned(),
+                source,
+            }),
+        }
+    }
+
+    /// The submodules listed in `.gitmodules`.
+    pub fn submodules(&self) -> Result<Vec<submodule::Submodule>, config::Error> {
+        self.config_get_regexp(r"^submodule\.", config::Scope::Modules)
+            .map(submodule::Submodule::from_config)
+    }
+
+    pub fn config_set(
+        &self,
+        key: &str,
+        value: impl AsRef<std::ffi::OsStr>,
+        scope: config::Scope,
+    ) -> Result<(), config::Error> {
+        self.command()
+            .with_arg("config")
+            .with_args(scope.args())
+            .with_arg(key)
+            .with_arg(value)
+            .run_and_wait_for_output()
+            .map_err(|source| config::Error::SetFailed {
+                key: key.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// Removes `key` from `scope`. This succeeds if `key` wasn't set.
+    pub fn config_unset(&self, key: &str, scope: config::Scope) -> Result<(), config::Error> {
+        match self
+            .command()
+            .with_arg("config")
+            .with_args(scope.args())
+            .with_arg("--unset")
+            .with_arg(key)
+            .run_and_wait_for_output()
+        {
+            // `--unset` exits with 5 if the key isn't set.
+            Ok(_) => Ok(()),
+            Err(err) if err.code() == Some(5) => Ok(()),
+            Err(source) => Err(config::Error::UnsetFailed {
+                key: key.to_owned(),
+                source,
+            }),
+        }
+    }
+
     pub fn modules(&self) -> std::io::Result<Option<String>> {
         let path = self.root.join(".gitmodules");
         if path.exists() {
@@ -54,13 +518,13 @@ impl<'a> Git<'a> {
 
     pub fn user_name(&self) -> bossy::Result<bossy::Output> {
         self.command()
-            .with_args(&["config", "user.name"])
+            .with_args(["config", "user.name"])
             .run_and_wait_for_output()
     }
 
     pub fn user_email(&self) -> bossy::Result<bossy::Output> {
         self.command()
-            .with_args(&["config", "user.email"])
+            .with_args(["config", "user.email"])
             .run_and_wait_for_output()
     }
 }
@@ -0,0 +1,248 @@
+use crate::repo::Error;
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read as _, Seek as _, SeekFrom, Write as _},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// What [`crate::repo::Repo::update`] (along with
+/// [`crate::repo::Repo::update_pinned`] and
+/// [`crate::repo::Repo::fetch_bundle`]) and [`crate::repo::Repo::status`] do
+/// when another process (or thread) is already updating or checking the same
+/// repo. Without this, their commands trip over each other's git locks, or
+/// worse, one resets the working tree halfway through the other's fetch.
+///
+/// This is an advisory lock on a file kept in the git directory of whatever
+/// repo contains this one (like a superproject), or next to the repo if
+/// nothing does, so it only keeps out other users of this crate, not git
+/// itself. It's held for the whole operation and released even if the
+/// process crashes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LockPolicy {
+    /// Wait as long as it takes for the other operation to finish. This
+    /// can't be cancelled.
+    #[default]
+    Wait,
+    /// Wait at most this long, then fail with
+    /// [`crate::repo::Error::RepoLocked`].
+    WaitFor(Duration),
+    /// Fail with [`crate::repo::Error::RepoLocked`] right away.
+    Fail,
+    /// Don't lock at all.
+    Off,
+}
+
+/// How often to check whether the lock's free when waiting for a limited
+/// time.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+thread_local! {
+    /// The lock files this thread holds, so that we know whether git's own
+    /// locks in a repo could belong to someone else.
+    static HELD: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Held until dropped, which closes the file and so releases the lock.
+#[derive(Debug)]
+pub(crate) struct RepoLock {
+    _file: File,
+    path: PathBuf,
+    registered: bool,
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if self.registered {
+            self.unregister();
+        }
+    }
+}
+
+/// The git directory of the repo whose working tree is `dir`, if there is
+/// one. We look for it ourselves rather than asking git, since that'd be
+/// one more command for every lock.
+fn git_dir_at(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    // Submodules and linked worktrees have a `.git` file pointing elsewhere.
+    let contents = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = contents.strip_prefix("gitdir:")?.trim();
+    Some(dir.join(git_dir))
+}
+
+/// i.e. ".git/hit-locks/deps/my-sdk.lock" for "deps/my-sdk" in a
+/// superproject, so that it never shows up as an untracked file, or
+/// "deps/.my-sdk.hit-lock" if "deps" isn't in a repo. This can't go inside the
+/// repo itself, since we need it before cloning, and atomic updates swap the
+/// whole directory out.
+fn lock_path(repo: &Path) -> std::io::Result<PathBuf> {
+    let repo = std::path::absolute(repo)?;
+    let (parent, name) = match (repo.parent(), repo.file_name()) {
+        (Some(parent), Some(name)) => (parent, name.to_string_lossy()),
+        _ => return Ok(repo.join(".hit-lock")),
+    };
+    let enclosing = parent
+        .ancestors()
+        .find_map(|dir| git_dir_at(dir).map(|git_dir| (dir, git_dir)));
+    Ok(match enclosing {
+        Some((root, git_dir)) => git_dir
+            .join("hit-locks")
+            .join(parent.strip_prefix(root).unwrap_or(parent))
+            .join(format!("{}.lock", name)),
+        None => parent.join(format!(".{}.hit-lock", name)),
+    })
+}
+
+/// The PID the holder wrote into the lock file, if we can read it.
+fn holder_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+impl RepoLock {
+    /// Takes the lock for `repo` according to `policy`, returning `None` if
+    /// the policy is [`LockPolicy::Off`].
+    pub fn acquire(repo: &Path, policy: LockPolicy) -> Result<Option<Self>, Error> {
+        if policy == LockPolicy::Off {
+            return Ok(None);
+        }
+        let path = lock_path(repo).map_err(|source| Error::LockFailed {
+            path: repo.to_owned(),
+            source,
+        })?;
+        let failed = |source| Error::LockFailed {
+            path: path.clone(),
+            source,
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent).map_err(failed)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(failed)?;
+        let acquired = match file.try_lock() {
+            Ok(()) => true,
+            Err(TryLockError::WouldBlock) => Self::wait(&file, repo, policy).map_err(failed)?,
+            Err(TryLockError::Error(source)) => return Err(failed(source)),
+        };
+        if !acquired {
+            return Err(Error::RepoLocked {
+                path: repo.to_owned(),
+                holder_pid: holder_pid(&mut file),
+            });
+        }
+        // This is only for error messages, so it's fine if it fails.
+        if let Err(err) = file
+            .set_len(0)
+            .and_then(|()| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| write!(file, "{}", std::process::id()))
+        {
+            log::warn!("failed to write PID to lock file {:?}: {}", path, err);
+        }
+        HELD.with(|held| held.borrow_mut().push(path.clone()));
+        Ok(Some(Self {
+            _file: file,
+            path,
+            registered: true,
+        }))
+    }
+
+    /// Stops counting this thread as the holder, for a lock that's about to
+    /// be carried off to other threads. [`RepoLock::is_held`] can't answer
+    /// for those, so whoever has the lock needs to keep track of that.
+    #[cfg(feature = "tokio")]
+    pub fn detach(&mut self) {
+        self.unregister();
+    }
+
+    fn unregister(&mut self) {
+        self.registered = false;
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(index) = held.iter().position(|path| *path == self.path) {
+                held.swap_remove(index);
+            }
+        });
+    }
+
+    /// Whether this thread holds the lock for `repo`.
+    pub fn is_held(repo: &Path) -> bool {
+        lock_path(repo)
+            .map(|path| HELD.with(|held| held.borrow().contains(&path)))
+            .unwrap_or(false)
+    }
+
+    /// Waits for someone else's lock to be released, returning whether we got
+    /// it before `policy` gave up.
+    fn wait(file: &File, repo: &Path, policy: LockPolicy) -> std::io::Result<bool> {
+        let timeout = match policy {
+            LockPolicy::Wait => None,
+            LockPolicy::WaitFor(timeout) => Some(timeout),
+            LockPolicy::Fail | LockPolicy::Off => return Ok(false),
+        };
+        log::info!("waiting for another process to finish with {:?}", repo);
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return file.lock().map(|()| true),
+        };
+        let start = Instant::now();
+        while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+            std::thread::sleep(remaining.min(LOCK_POLL_INTERVAL));
+            match file.try_lock() {
+                Ok(()) => return Ok(true),
+                Err(TryLockError::WouldBlock) => (),
+                Err(TryLockError::Error(err)) => return Err(err),
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+
+    #[test]
+    fn is_held_only_by_the_locking_thread() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        assert!(!RepoLock::is_held(&repo));
+        let lock = RepoLock::acquire(&repo, LockPolicy::Fail).unwrap();
+        assert!(lock.is_some());
+        assert!(RepoLock::is_held(&repo));
+        assert!(!RepoLock::is_held(&dir.path().join("other")));
+        let elsewhere = repo.clone();
+        assert!(!std::thread::spawn(move || RepoLock::is_held(&elsewhere))
+            .join()
+            .unwrap());
+        drop(lock);
+        assert!(!RepoLock::is_held(&repo));
+        #[cfg(feature = "tokio")]
+        {
+            let mut lock = RepoLock::acquire(&repo, LockPolicy::Fail).unwrap().unwrap();
+            lock.detach();
+            assert!(!RepoLock::is_held(&repo));
+        }
+        assert!(RepoLock::acquire(&repo, LockPolicy::Off).unwrap().is_none());
+        assert!(!RepoLock::is_held(&repo));
+    }
+}
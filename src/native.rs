@@ -0,0 +1,99 @@
+//! Read-only queries answered by `gix` in-process, to save spawning git. Each
+//! of these returns `None` whenever it can't give a definite answer, so that
+//! the caller falls back to running git, which takes care of reporting
+//! errors.
+
+use crate::Git;
+use gix::remote::Direction;
+
+/// If commands don't simply run the `git` on `PATH` with our defaults, we
+/// can't be sure we'd see what they would.
+fn enabled(git: Git<'_>) -> bool {
+    git.backend().is_none()
+        && git.program() == std::path::Path::new("git")
+        && git.env().is_empty()
+        && git.auth().is_none()
+}
+
+pub(crate) fn open(git: Git<'_>) -> Option<gix::Repository> {
+    if !enabled(git) {
+        return None;
+    }
+    gix::open(git.root())
+        .map_err(|err| log::debug!("failed to open {:?} with gix: {}", git.root(), err))
+        .ok()
+}
+
+pub(crate) fn head_hash(repo: &gix::Repository) -> Option<String> {
+    repo.head_id().ok().map(|id| id.to_string())
+}
+
+/// The same as `git log -1 --pretty=%s`.
+pub(crate) fn latest_subject(repo: &gix::Repository) -> Option<String> {
+    let commit = repo.head_commit().ok()?;
+    let message = commit.message().ok()?;
+    String::from_utf8(message.summary().to_vec())
+        .ok()
+        .map(|subject| subject.trim().to_owned())
+}
+
+/// The same as `git rev-list --left-right --count HEAD...<base>`.
+pub(crate) fn ahead_behind(repo: &gix::Repository, base: &str) -> Option<(u32, u32)> {
+    let head = repo.head_id().ok()?.detach();
+    let base = repo
+        .rev_parse_single(base)
+        .ok()?
+        .object()
+        .ok()?
+        .peel_to_commit()
+        .ok()?
+        .id;
+    let count = |tip, hidden| {
+        repo.rev_walk([tip])
+            .with_hidden([hidden])
+            .all()
+            .ok()?
+            .try_fold(0, |count, info| info.ok().map(|_| count + 1))
+    };
+    Some((count(head, base)?, count(base, head)?))
+}
+
+/// The same as [`crate::repo::Repo::upstream_base`], and likewise `None` if
+/// nothing's tracked.
+pub(crate) fn upstream_base(repo: &gix::Repository, remote: &str) -> Option<String> {
+    let head = repo.head_name().ok()??;
+    // `@{u}` only resolves if the remote-tracking branch exists.
+    let tracking = repo
+        .branch_remote_tracking_ref_name(head.as_ref(), Direction::Fetch)?
+        .ok()?;
+    repo.try_find_reference(tracking.as_ref()).ok()??;
+    let merge = repo
+        .branch_remote_ref_name(head.as_ref(), Direction::Fetch)?
+        .ok()?;
+    let branch = std::str::from_utf8(merge.as_bstr().strip_prefix(b"refs/heads/")?).ok()?;
+    Some(format!("refs/remotes/{}/{}", remote, branch))
+}
+
+/// Whether `.gitmodules` has a path for submodule `name`.
+pub(crate) fn in_index(git: Git<'_>, name: &str) -> Option<bool> {
+    if !enabled(git) {
+        return None;
+    }
+    let modules = gix::config::File::from_path_no_includes(
+        git.root().join(".gitmodules"),
+        gix::config::Source::Worktree,
+    )
+    .ok()?;
+    Some(modules.string_by("submodule", name, "path").is_some())
+}
+
+/// Whether the repo's own config has a URL for submodule `name`.
+pub(crate) fn initialized(git: Git<'_>, name: &str) -> Option<bool> {
+    let repo = open(git)?;
+    let config = gix::config::File::from_path_no_includes(
+        repo.common_dir().join("config"),
+        gix::config::Source::Local,
+    )
+    .ok()?;
+    Some(config.string_by("submodule", name, "url").is_some())
+}
@@ -0,0 +1,77 @@
+use once_cell_regex::regex;
+
+/// What git was busy with when it reported progress.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Working out which objects need to be sent. This is usually done by
+    /// the remote.
+    Counting,
+    /// Compressing objects before sending them. This is usually done by the
+    /// remote.
+    Compressing,
+    /// Downloading objects.
+    Receiving,
+    /// Reconstructing objects that were sent as deltas.
+    Resolving,
+    /// Writing files into the working tree.
+    CheckingOut,
+    /// Anything else, by the name git gave it.
+    Other(String),
+}
+
+impl Phase {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "Counting objects" | "Enumerating objects" => Self::Counting,
+            "Compressing objects" => Self::Compressing,
+            "Receiving objects" => Self::Receiving,
+            "Resolving deltas" => Self::Resolving,
+            "Updating files" | "Checking out files" => Self::CheckingOut,
+            _ => Self::Other(name.to_owned()),
+        }
+    }
+}
+
+/// A progress report from a clone or fetch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgressEvent {
+    pub phase: Phase,
+    /// `None` if git didn't know how much work there was in total.
+    pub percent: Option<u8>,
+    /// How many objects (or deltas, or files) are done so far.
+    pub objects: u64,
+    pub total_objects: Option<u64>,
+    /// How much has been received so far. git only reports this while
+    /// receiving objects, and rounds it to a couple of decimal places.
+    pub bytes: Option<u64>,
+}
+
+impl ProgressEvent {
+    /// Parses a progress line from git's stderr, like
+    /// "Receiving objects:  42% (420/1000), 1.20 MiB | 2.00 MiB/s".
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let caps = regex!(
+            r"^(?:remote: )?(?P<phase>[A-Za-z][A-Za-z ]*?):\s+(?:(?P<percent>\d+)% \()?(?P<objects>\d+)(?:/(?P<total>\d+)\))?(?:, (?P<size>\d+(?:\.\d+)?) (?P<unit>bytes?|KiB|MiB|GiB|TiB))?"
+        )
+        .captures(line)?;
+        let number = |name| caps.name(name).and_then(|m| m.as_str().parse::<u64>().ok());
+        let bytes = caps.name("size").and_then(|size| {
+            let size: f64 = size.as_str().parse().ok()?;
+            let scale = match caps.name("unit")?.as_str() {
+                "KiB" => 1u64 << 10,
+                "MiB" => 1 << 20,
+                "GiB" => 1 << 30,
+                "TiB" => 1 << 40,
+                _ => 1,
+            };
+            Some((size * scale as f64) as u64)
+        });
+        Some(Self {
+            phase: Phase::from_name(&caps["phase"]),
+            percent: caps.name("percent").and_then(|m| m.as_str().parse().ok()),
+            objects: number("objects")?,
+            total_objects: number("total"),
+            bytes,
+        })
+    }
+}
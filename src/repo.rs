@@ -1,15 +1,51 @@
-use crate::Git;
-use std::path::{Path, PathBuf};
+use crate::{
+    lock::RepoLock, progress::ProgressEvent, version::GitVersion, Auth, CancellationToken,
+    CommandExt as _, Git, GitBackend, Limits, LockPolicy, OutputMode, Progress, RetryPolicy,
+    Stopped,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fmt::{self, Display},
+    hash::{Hash, Hasher},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use thiserror::Error;
 
+/// `git sparse-checkout` and `git clone --sparse` only exist as of git 2.25.
+/// Until 2.35, only `sparse-checkout init` takes `--cone`, not `set`.
+const SPARSE_CHECKOUT_VERSION: GitVersion = GitVersion::new(2, 25, 0);
+
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Failed to fetch repo: {0}")]
-    FetchFailed(#[source] bossy::Error),
+    #[error("Failed to fetch from remote {remote:?}: {source}")]
+    FetchFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to fetch {refspecs:?} from remote {remote:?}: {source}")]
+    FetchRefspecsFailed {
+        remote: String,
+        refspecs: Vec<String>,
+        source: bossy::Error,
+    },
     #[error("Failed to get checkout revision: {0}")]
     RevParseLocalFailed(#[source] bossy::Error),
     #[error("Failed to get upstream revision: {0}")]
     RevParseRemoteFailed(#[source] bossy::Error),
+    #[error("The checked out branch has no upstream configured.")]
+    NoUpstream,
+    #[error("Failed to count commits: {0}")]
+    RevListFailed(#[source] bossy::Error),
+    #[error("Failed to parse output of `{command}`: {output:?}")]
+    ParseFailed {
+        command: &'static str,
+        output: String,
+    },
     #[error("Failed to get commit log: {0}")]
     LogFailed(#[source] bossy::Error),
     #[error("Failed to create parent directory {path:?}: {source}")]
@@ -17,128 +53,5073 @@ pub enum Error {
         path: PathBuf,
         source: std::io::Error,
     },
+    #[error("{path:?} exists but isn't a repo; use the `force` option to replace it anyway.")]
+    NotARepo { path: PathBuf },
+    #[error("Failed to check whether {path:?} is a repo: {source}")]
+    ValidityCheckFailed { path: PathBuf, source: bossy::Error },
+    #[error("Failed to remove invalid repo at {path:?}: {source}")]
+    InvalidRepoRemovalFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to init repo: {0}")]
+    InitFailed(#[source] bossy::Error),
     #[error("Failed to clone repo: {0}")]
     CloneFailed(#[source] bossy::Error),
+    #[error("Failed to list refs: {0}")]
+    RefListFailed(#[source] bossy::Error),
+    #[error("Authentication with remote {url:?} failed: {source}")]
+    RemoteAuthFailed {
+        url: std::ffi::OsString,
+        source: bossy::Error,
+    },
+    #[error("Remote {url:?} is unreachable: {source}")]
+    RemoteUnreachable {
+        url: std::ffi::OsString,
+        source: bossy::Error,
+    },
+    #[error("Remote {url:?} isn't a git repository: {source}")]
+    RemoteNotFound {
+        url: std::ffi::OsString,
+        source: bossy::Error,
+    },
+    #[error("Remote {url:?} has no `HEAD`; it might be empty.")]
+    RemoteHeadMissing { url: std::ffi::OsString },
+    #[error("Failed to verify remote {url:?}: {source}")]
+    RemoteVerifyFailed {
+        url: std::ffi::OsString,
+        source: bossy::Error,
+    },
+    #[error("Failed to get URL of remote {remote:?}: {source}")]
+    RemoteUrlFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to update URL of remote {remote:?} to {url:?}: {source}")]
+    RemoteUrlUpdateFailed {
+        remote: String,
+        url: std::ffi::OsString,
+        source: bossy::Error,
+    },
+    #[error("Failed to create tag {name:?}: {source}")]
+    TagCreateFailed { name: String, source: bossy::Error },
+    #[error("Failed to delete tag {name:?}: {source}")]
+    TagDeleteFailed { name: String, source: bossy::Error },
+    #[error("Failed to describe `HEAD`: {0}")]
+    DescribeFailed(#[source] bossy::Error),
+    #[error("No tags are reachable from `HEAD`, but this is a shallow clone, so they might just be missing.")]
+    DescribeShallow,
+    #[error("Failed to get current branch: {0}")]
+    CurrentBranchFailed(#[source] bossy::Error),
+    #[error("Failed to checkout revision {rev:?}: {source}")]
+    CheckoutFailed { rev: String, source: bossy::Error },
+    #[error("Failed to create branch {branch:?}: {source}")]
+    BranchCreateFailed {
+        branch: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to switch to branch {branch:?}: {source}")]
+    SwitchFailed {
+        branch: String,
+        source: bossy::Error,
+    },
+    #[error("Switching to branch {branch:?} would overwrite changes to {paths:?}.")]
+    WouldOverwrite { branch: String, paths: Vec<PathBuf> },
+    #[error("Failed to delete branch {branch:?}: {source}")]
+    BranchDeleteFailed {
+        branch: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to check if repo is shallow: {0}")]
+    ShallowCheckFailed(#[source] bossy::Error),
+    #[error("Failed to fetch full history from remote {remote:?}: {source}")]
+    UnshallowFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to diff {range:?}: {source}")]
+    DiffFailed { range: String, source: bossy::Error },
+    #[error("Failed to blame {path:?}: {source}")]
+    BlameFailed { path: PathBuf, source: bossy::Error },
+    #[error("Failed to grep for {pattern:?}: {source}")]
+    GrepFailed {
+        pattern: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to list files: {0}")]
+    LsFilesFailed(#[source] bossy::Error),
+    #[error("Failed to check if paths are ignored: {0}")]
+    CheckIgnoreFailed(#[source] bossy::Error),
+    #[error("Failed to archive {rev:?}: {source}")]
+    ArchiveFailed { rev: String, source: bossy::Error },
+    #[error("Failed to prepare archive destination {path:?}: {source}")]
+    ArchiveDestFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to extract archive into {dest:?}: {source}")]
+    ArchiveExtractFailed { dest: PathBuf, source: bossy::Error },
+    #[error("Failed to list remotes: {0}")]
+    RemoteListFailed(#[source] bossy::Error),
+    #[error("Failed to add remote {remote:?}: {source}")]
+    RemoteAddFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to remove remote {remote:?}: {source}")]
+    RemoteRemoveFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to rename remote {old:?} to {new:?}: {source}")]
+    RemoteRenameFailed {
+        old: String,
+        new: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to resolve bundle path {path:?}: {source}")]
+    BundlePathFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to create bundle at {path:?}: {source}")]
+    BundleCreateFailed { path: PathBuf, source: bossy::Error },
+    #[error("Bundle at {path:?} is invalid: {reason}")]
+    BundleInvalid { path: PathBuf, reason: String },
+    #[error("Failed to list refs in bundle at {path:?}: {source}")]
+    BundleListFailed { path: PathBuf, source: bossy::Error },
+    #[error("Failed to fetch from bundle at {path:?}: {source}")]
+    BundleFetchFailed { path: PathBuf, source: bossy::Error },
+    #[error("Failed to check repo integrity: {0}")]
+    FsckFailed(#[source] bossy::Error),
+    #[error("Repo at {path:?} is still corrupt after cloning again: {} missing objects, errors: {:?}", report.missing.len(), report.errors)]
+    Corrupt { path: PathBuf, report: FsckReport },
+    #[error("{0}")]
+    GitUnavailable(#[source] crate::version::Error),
+    #[error("Failed to get sparse checkout configuration: {0}")]
+    SparseCheckFailed(#[source] bossy::Error),
+    #[error("Failed to set sparse checkout paths to {paths:?}: {source}")]
+    SparseSetFailed {
+        paths: Vec<String>,
+        source: bossy::Error,
+    },
+    #[error("Failed to stage {paths:?}: {source}")]
+    AddFailed {
+        paths: Vec<PathBuf>,
+        source: bossy::Error,
+    },
+    #[error("Failed to check for staged changes: {0}")]
+    StagedCheckFailed(#[source] bossy::Error),
+    #[error("Nothing is staged, so there's nothing to commit.")]
+    NothingToCommit,
+    #[error("Failed to commit: {0}")]
+    CommitFailed(#[source] bossy::Error),
+    #[error("Remote {remote:?} rejected the push: {rejections:?}")]
+    PushRejected {
+        remote: String,
+        /// Each rejected ref, along with git's reason.
+        rejections: Vec<String>,
+    },
+    #[error("Failed to push to remote {remote:?}: {source}")]
+    PushFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to resolve patch path {path:?}: {source}")]
+    PatchPathFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to apply patch {patch:?}: {source}")]
+    ApplyFailed {
+        patch: PathBuf,
+        source: bossy::Error,
+    },
+    #[error("Patch {patch:?} doesn't apply to {paths:?}.")]
+    PatchRejected { patch: PathBuf, paths: Vec<PathBuf> },
+    #[error("Failed to format patches for {range:?}: {source}")]
+    FormatPatchFailed { range: String, source: bossy::Error },
+    #[error("Failed to add worktree at {path:?}: {source}")]
+    WorktreeAddFailed { path: PathBuf, source: bossy::Error },
+    #[error("Failed to resolve worktree path {path:?}: {source}")]
+    WorktreePathFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to list worktrees: {0}")]
+    WorktreeListFailed(#[source] bossy::Error),
+    #[error("Failed to remove worktree at {path:?}: {source}")]
+    WorktreeRemoveFailed { path: PathBuf, source: bossy::Error },
+    #[error("Failed to garbage collect repo: {0}")]
+    GcFailed(#[source] bossy::Error),
+    #[error("Failed to prune repo: {0}")]
+    PruneFailed(#[source] bossy::Error),
+    #[error("Failed to repack repo: {0}")]
+    RepackFailed(#[source] bossy::Error),
+    #[error("Failed to get working tree status: {0}")]
+    StatusFailed(#[source] bossy::Error),
+    #[error("Refusing to discard uncommitted changes to {paths:?}; use the `force` option to update anyway.")]
+    DirtyWorkingTree { paths: Vec<PathBuf> },
+    #[error("Failed to update submodules: {0}")]
+    SubmoduleUpdateFailed(#[source] bossy::Error),
+    #[error("Failed to list submodules: {0}")]
+    SubmoduleListFailed(#[source] crate::config::Error),
+    #[error("Failed to stash local changes: {0}")]
+    StashFailed(#[source] bossy::Error),
+    #[error("Failed to reapply stash entry {index}: {source}")]
+    StashReapplyFailed { index: usize, source: bossy::Error },
+    #[error("Failed to drop stash entry {index}: {source}")]
+    StashDropFailed { index: usize, source: bossy::Error },
+    #[error("Failed to list stash entries: {0}")]
+    StashListFailed(#[source] bossy::Error),
+    #[error("Reapplying local changes conflicted in {paths:?}; they're still in the stash, so resolve the conflicts and drop the stash manually.")]
+    StashConflict { paths: Vec<PathBuf> },
+    #[error("Failed to check for conflicts: {0}")]
+    ConflictCheckFailed(#[source] bossy::Error),
+    #[error("Failed to abort after conflicts: {0}")]
+    AbortFailed(#[source] bossy::Error),
+    #[error("Failed to pull from remote {remote:?}: {source}")]
+    PullFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Rebasing conflicted in {paths:?}, so it was aborted.")]
+    RebaseConflict { paths: Vec<PathBuf> },
+    #[error("Failed to merge {rev:?}: {source}")]
+    MergeFailed { rev: String, source: bossy::Error },
+    #[error("Merging {rev:?} conflicted in {paths:?}.")]
+    MergeConflict { rev: String, paths: Vec<PathBuf> },
+    #[error("Failed to find merge base of {a:?} and {b:?}: {source}")]
+    MergeBaseFailed {
+        a: String,
+        b: String,
+        source: bossy::Error,
+    },
+    #[error("{a:?} and {b:?} have no common ancestor.")]
+    NoMergeBase { a: String, b: String },
+    #[error("Failed to cherry-pick {revs:?}: {source}")]
+    CherryPickFailed {
+        revs: Vec<String>,
+        source: bossy::Error,
+    },
+    #[error("Cherry-picking {commit:?} conflicted in {paths:?}.")]
+    CherryPickConflict { commit: String, paths: Vec<PathBuf> },
+    #[error("Failed to revert {rev:?}: {source}")]
+    RevertFailed { rev: String, source: bossy::Error },
+    #[error("Reverting {commit:?} conflicted in {paths:?}.")]
+    RevertConflict { commit: String, paths: Vec<PathBuf> },
+    #[error("Failed to check if commit {rev:?} is present: {source}")]
+    CommitCheckFailed { rev: String, source: bossy::Error },
+    #[error("Failed to check if {ancestor:?} is an ancestor of {descendant:?}: {source}")]
+    AncestryCheckFailed {
+        ancestor: String,
+        descendant: String,
+        source: bossy::Error,
+    },
+    #[error("Only {count} commits are reachable from {rev:?} since the clone is shallow; unshallow it to get the full count.")]
+    CountTruncated { rev: String, count: u64 },
+    #[error("Revision {rev:?} doesn't exist.")]
+    RevisionNotFound { rev: String },
     #[error("Failed to reset repo: {0}")]
     ResetFailed(#[source] bossy::Error),
     #[error("Failed to clean repo: {0}")]
     CleanFailed(#[source] bossy::Error),
+    #[error("Failed to query default branch of remote {remote:?}: {source}")]
+    LsRemoteFailed {
+        remote: String,
+        source: bossy::Error,
+    },
+    #[error("Failed to resolve default branch of remote {remote:?}; please specify a branch explicitly.")]
+    DefaultBranchUnresolved { remote: String },
+    #[error("Failed to get upstream of checked out branch: {0}")]
+    UpstreamCheckFailed(#[source] bossy::Error),
+    #[error("Failed to set upstream of checked out branch to \"{remote}/{branch}\": {source}")]
+    UpstreamSetFailed {
+        remote: String,
+        branch: String,
+        source: bossy::Error,
+    },
+    #[error("Command {command:?} timed out after {elapsed:?}.")]
+    TimedOut { command: String, elapsed: Duration },
+    #[error("Command {command:?} was cancelled.")]
+    Cancelled { command: String },
+    #[error("Command {command:?} needs credentials, but prompting for them is disabled: {source}")]
+    AuthenticationRequired {
+        command: String,
+        source: bossy::Error,
+    },
+    #[error("Gave up after {attempts} attempts: {source}")]
+    GaveUp {
+        command: String,
+        attempts: u32,
+        source: bossy::Error,
+    },
+    #[error("Repo at {path:?} is in use by another process{}.", holder_pid.map(|pid| format!(" (PID {})", pid)).unwrap_or_default())]
+    RepoLocked {
+        path: PathBuf,
+        /// `None` if the holder's PID couldn't be read.
+        holder_pid: Option<u32>,
+    },
+    #[error("Failed to lock {path:?}: {source}")]
+    LockFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to find the repo containing {start:?}: {source}")]
+    DiscoverFailed {
+        start: PathBuf,
+        source: bossy::Error,
+    },
 }
 
-#[derive(Clone, Copy, Debug)]
+/// The paths git lists when it refuses to check something out over local
+/// changes or untracked files, i.e.
+/// "error: Your local changes to the following files would be overwritten by
+/// checkout:" followed by tab-indented paths.
+fn overwritten_paths(err: &bossy::Error) -> Vec<PathBuf> {
+    let stderr = err
+        .stderr()
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+    let mut paths = Vec::new();
+    let mut listing = false;
+    for line in stderr.lines() {
+        if line.contains("would be overwritten by") {
+            listing = true;
+        } else if let Some(path) = line.strip_prefix('\t').filter(|_| listing) {
+            paths.push(PathBuf::from(path));
+        } else {
+            listing = false;
+        }
+    }
+    paths
+}
+
+/// Cone mode lists its directories sorted and without slashes at either
+/// end, so we compare them the same way to avoid setting them again when
+/// nothing's changed.
+fn normalize_sparse_paths(paths: &[String]) -> Vec<String> {
+    let mut paths = paths
+        .iter()
+        .map(|path| path.trim_matches('/').to_owned())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Whether `err` is git telling us there's no upstream to resolve `@{u}` to,
+/// rather than failing for some other reason.
+fn no_upstream(err: &bossy::Error) -> bool {
+    err.stderr()
+        .map(String::from_utf8_lossy)
+        .filter(|stderr| {
+            stderr.contains("no upstream configured for branch")
+                || stderr.contains("HEAD does not point to a branch")
+        })
+        .is_some()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RemoteFailure {
+    Auth,
+    Unreachable,
+    NotFound,
+    Other,
+}
+
+impl RemoteFailure {
+    /// Takes a guess at why talking to a remote failed based on git's stderr.
+    fn classify(err: &bossy::Error) -> Self {
+        let stderr = err
+            .stderr()
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        let any = |patterns: &[&str]| patterns.iter().any(|pattern| stderr.contains(pattern));
+        if any(&[
+            "Authentication failed",
+            "could not read Username",
+            "could not read Password",
+            "terminal prompts disabled",
+            "Permission denied",
+            "Host key verification failed",
+            "The requested URL returned error: 401",
+            "The requested URL returned error: 403",
+        ]) {
+            Self::Auth
+        } else if any(&[
+            "Could not resolve host",
+            "Could not resolve hostname",
+            "Connection refused",
+            "Connection timed out",
+            "Operation timed out",
+            "Network is unreachable",
+            "No route to host",
+            "Failed to connect",
+        ]) {
+            Self::Unreachable
+        } else if any(&[
+            "does not appear to be a git repository",
+            "Repository not found",
+            "not found",
+        ]) {
+            Self::NotFound
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Converts a failure into the matching error variant, using `other` for
+    /// failures that don't fit any of them.
+    fn error(
+        url: std::ffi::OsString,
+        source: bossy::Error,
+        other: impl FnOnce(std::ffi::OsString, bossy::Error) -> Error,
+    ) -> Error {
+        match Self::classify(&source) {
+            Self::Auth => Error::RemoteAuthFailed { url, source },
+            Self::Unreachable => Error::RemoteUnreachable { url, source },
+            Self::NotFound => Error::RemoteNotFound { url, source },
+            Self::Other => other(url, source),
+        }
+    }
+}
+
+/// What [`Repo::update`] does with local changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum UpdateStrategy {
+    /// Refuse to update a dirty working tree unless `force` is set, in which
+    /// case local changes are discarded.
+    #[default]
+    HardReset,
+    /// Stash local changes (including untracked files) before updating, and
+    /// reapply them afterward. This has no effect on atomic updates, since
+    /// those always start from a fresh clone.
+    StashAndReapply,
+    /// Rebase local commits onto the updated branch instead of discarding
+    /// them; see [`Repo::pull_rebase`]. Untracked files are left alone.
+    Rebase,
+}
+
+/// What kind of clone a [`Repo`] is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum RepoKind {
+    /// A normal clone with a working tree.
+    #[default]
+    Checkout,
+    /// A clone without a working tree, whose branches are kept in sync with
+    /// the remote's branches.
+    Bare,
+    /// A bare clone that keeps every ref in sync with the remote.
+    Mirror,
+}
+
+impl RepoKind {
+    pub fn has_working_tree(self) -> bool {
+        matches!(self, Self::Checkout)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum Status {
-    Stale,
+    /// The repo hasn't been cloned yet.
+    Missing,
+    /// `HEAD` matches the upstream.
     Fresh,
+    /// `HEAD` has this many commits that the upstream doesn't.
+    Ahead(u32),
+    /// The upstream has this many commits that `HEAD` doesn't.
+    Behind(u32),
+    Diverged {
+        ahead: u32,
+        behind: u32,
+    },
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing"),
+            Self::Fresh => write!(f, "fresh"),
+            Self::Ahead(ahead) => write!(f, "{} ahead", ahead),
+            Self::Behind(behind) => write!(f, "{} behind", behind),
+            Self::Diverged { ahead, behind } => {
+                write!(f, "diverged ({} ahead, {} behind)", ahead, behind)
+            }
+        }
+    }
 }
 
 impl Status {
+    fn from_counts(ahead: u32, behind: u32) -> Self {
+        match (ahead, behind) {
+            (0, 0) => Self::Fresh,
+            (ahead, 0) => Self::Ahead(ahead),
+            (0, behind) => Self::Behind(behind),
+            (ahead, behind) => Self::Diverged { ahead, behind },
+        }
+    }
+
+    /// Whether the repo is missing commits from the upstream, and would thus
+    /// be changed by [`Repo::update`].
     pub fn stale(self) -> bool {
-        matches!(self, Self::Stale)
+        matches!(
+            self,
+            Self::Missing | Self::Behind(_) | Self::Diverged { .. }
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CloneOptions {
+    /// How many commits of history to fetch; `None` fetches everything.
+    pub depth: Option<u32>,
+    pub single_branch: bool,
+    /// Clone and update submodules as well. If `depth` is set, submodules
+    /// are cloned shallowly too.
+    pub recurse_submodules: bool,
+    /// A partial clone filter spec, i.e. `blob:none` for a blobless clone.
+    pub filter: Option<String>,
+    /// A local repo to borrow objects from when cloning, if it exists. git
+    /// ignores it if it's a shallow clone.
+    pub reference: Option<PathBuf>,
+    /// Copy borrowed objects once the clone is done, so the clone doesn't
+    /// break if `reference` is later deleted.
+    pub dissociate: bool,
+    /// Only check out these directories, using a cone-mode sparse checkout.
+    /// If unset, [`Repo::update`] keeps whatever sparse configuration the
+    /// repo already has, even when it needs to clone again.
+    pub sparse_paths: Option<Vec<String>>,
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        Self {
+            depth: Some(1),
+            single_branch: true,
+            recurse_submodules: false,
+            filter: None,
+            reference: None,
+            dissociate: false,
+            sparse_paths: None,
+        }
+    }
+}
+
+impl CloneOptions {
+    pub fn full() -> Self {
+        Self {
+            depth: None,
+            single_branch: false,
+            ..Default::default()
+        }
+    }
+
+    fn add_depth_arg(&self, command: &mut bossy::Command) {
+        if let Some(depth) = self.depth {
+            command.add_arg("--depth").add_arg(depth.to_string());
+        }
+    }
+
+    /// Args shared by `clone` and `fetch`.
+    fn add_fetch_args(&self, command: &mut bossy::Command) {
+        self.add_depth_arg(command);
+        if let Some(filter) = &self.filter {
+            command.add_arg(format!("--filter={}", filter));
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct UpdateOptions {
+    /// The branch to track; `None` tracks the remote's default branch.
+    pub branch: Option<String>,
+    pub clone: CloneOptions,
+    /// Discard uncommitted changes instead of failing with
+    /// [`Error::DirtyWorkingTree`], and replace a directory that isn't a repo
+    /// instead of failing with [`Error::NotARepo`].
+    pub force: bool,
+    /// Patterns for untracked files that survive the post-reset clean. An
+    /// empty list cleans everything.
+    pub clean_excludes: Vec<String>,
+    /// Clone into a temporary sibling directory and swap it into place once
+    /// it's complete, so that readers never see a partially updated tree.
+    /// Since this always starts from a fresh clone, untracked files matching
+    /// `clean_excludes` don't survive. If the swap fails (i.e. because the
+    /// path is a mount point), this falls back to updating in place.
+    pub atomic: bool,
+    /// Run `git gc --auto` after updating. Failures are logged rather than
+    /// failing the update.
+    pub auto_gc: bool,
+    pub strategy: UpdateStrategy,
+    /// Run `git fsck` before and after updating, and clone again if the repo
+    /// is corrupt. A corrupt repo with uncommitted changes is only replaced
+    /// with `force`.
+    pub verify: bool,
+}
+
+impl Default for UpdateOptions {
+    fn default() -> Self {
+        Self {
+            branch: None,
+            clone: Default::default(),
+            force: false,
+            clean_excludes: vec!["/target".to_owned()],
+            atomic: false,
+            auto_gc: false,
+            strategy: Default::default(),
+            verify: false,
+        }
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct Repo {
-    path: PathBuf,
+pub struct Signature {
+    pub name: String,
+    pub email: String,
 }
 
-impl Repo {
-    pub fn from_path(path: impl Into<PathBuf>) -> Self {
-        Self { path: path.into() }
+#[derive(Clone, Debug, Default)]
+pub struct CommitOptions {
+    /// Commit even if nothing is staged, instead of failing with
+    /// [`Error::NothingToCommit`].
+    pub allow_empty: bool,
+    /// Replace the current `HEAD` commit instead of creating a new one.
+    pub amend: bool,
+    /// Overrides the configured author.
+    pub author: Option<Signature>,
+    /// Overrides the configured committer.
+    pub committer: Option<Signature>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MergeOptions {
+    /// Fail instead of creating a merge commit.
+    pub ff_only: bool,
+    /// Create a merge commit even if a fast-forward is possible.
+    pub no_ff: bool,
+    /// Stage the merged changes without committing or recording a merge.
+    pub squash: bool,
+    /// Leave conflicts in the working tree for the caller to resolve, rather
+    /// than aborting the merge.
+    pub leave_conflicts: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Only move the branch, keeping the index and working tree.
+    Soft,
+    /// Move the branch and reset the index, keeping the working tree.
+    Mixed,
+    /// Move the branch and reset both the index and working tree.
+    Hard,
+}
+
+impl ResetMode {
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Soft => "--soft",
+            Self::Mixed => "--mixed",
+            Self::Hard => "--hard",
+        }
     }
+}
 
-    pub fn path(&self) -> &Path {
-        &self.path
+/// Options for [`Repo::cherry_pick`] and [`Repo::revert`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PickOptions {
+    /// Apply the changes to the working tree and index without committing.
+    pub no_commit: bool,
+    /// Leave conflicts in the working tree for the caller to resolve, rather
+    /// than aborting the whole operation.
+    pub leave_conflicts: bool,
+}
+
+/// Why a cherry-pick or revert didn't complete.
+enum PickFailure {
+    Failed(bossy::Error),
+    Conflict { commit: String, paths: Vec<PathBuf> },
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ApplyOptions {
+    /// Fall back to a three-way merge if the patch doesn't apply cleanly.
+    pub three_way: bool,
+    /// Only check whether the patch applies, without changing anything.
+    pub check: bool,
+    /// Apply the patch to the index as well as the working tree.
+    pub index: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PushOptions {
+    /// Make the pushed branch track the remote branch.
+    pub set_upstream: bool,
+    /// Overwrite the remote ref, but only if it's still where our
+    /// remote-tracking ref says it is.
+    pub force_with_lease: bool,
+    /// Push tags as well.
+    pub tags: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MaintenanceOptions {
+    /// Run `git gc --auto`, which only does work once enough loose objects
+    /// have accumulated.
+    pub gc: bool,
+    /// Run `git prune` to delete unreachable loose objects.
+    pub prune: bool,
+    /// Run `git repack -ad` to pack everything into a single pack.
+    pub repack: bool,
+}
+
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        Self {
+            gc: true,
+            prune: false,
+            repack: false,
+        }
     }
+}
 
-    pub fn git(&self) -> Git<'_> {
-        Git::new(self.path())
+#[derive(Clone, Debug, Default)]
+pub struct FetchOptions {
+    /// The remote to fetch from; `None` uses the repo's remote.
+    pub remote: Option<String>,
+    /// What to fetch; empty uses the remote's configured refspecs.
+    pub refspecs: Vec<String>,
+    /// Delete remote-tracking refs that no longer exist on the remote.
+    pub prune: bool,
+    /// Fetch all tags, not just the ones pointing into fetched history.
+    pub tags: bool,
+    pub depth: Option<u32>,
+    /// Fetch the complete history of a shallow clone.
+    pub unshallow: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+    /// Extracts the archive into the destination directory.
+    Dir,
+}
+
+impl ArchiveFormat {
+    fn git_format(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar | Self::Dir => "tar",
+        }
     }
+}
 
-    pub fn status(&self) -> Result<Status, Error> {
-        let status = if !self.path().is_dir() {
-            Status::Stale
-        } else {
-            let git = self.git();
-            git.command_parse("fetch origin")
-                .run_and_wait()
-                .map_err(Error::FetchFailed)?;
-            let local = git
-                .command_parse("rev-parse HEAD")
-                .run_and_wait_for_output()
-                .map_err(Error::RevParseLocalFailed)?;
-            let remote = git
-                .command_parse("rev-parse @{u}")
-                .run_and_wait_for_output()
-                .map_err(Error::RevParseRemoteFailed)?;
-            if local.stdout() != remote.stdout() {
-                Status::Stale
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct InitOptions {
+    /// The name of the initial branch; `None` uses git's default.
+    pub initial_branch: Option<String>,
+    pub bare: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct UpdateOutcome {
+    /// `HEAD` before the update, or `None` if the repo was freshly cloned.
+    pub old_head: Option<String>,
+    pub new_head: String,
+    pub freshly_cloned: bool,
+}
+
+impl UpdateOutcome {
+    pub fn changed(&self) -> bool {
+        self.old_head.as_deref() != Some(self.new_head.as_str())
+    }
+}
+
+/// An update that's had everything but its clone or fetch done, so that
+/// [`Repo::update_async`] can wait for that part without tying up a thread.
+#[cfg(feature = "tokio")]
+struct PreparedUpdate {
+    options: UpdateOptions,
+    old_head: Option<String>,
+    transfer: Transfer,
+}
+
+#[cfg(feature = "tokio")]
+enum Transfer {
+    Clone,
+    Fetch,
+    FetchBare,
+}
+
+/// The same for [`Repo::status_async`]. Bare repos are compared against the
+/// remote's `HEAD`, while everything else is compared against its upstream.
+#[cfg(feature = "tokio")]
+struct PreparedStatus {
+    remote_head: Option<String>,
+    fetch: Option<bossy::Command>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Commit {
+    pub hash: String,
+    pub short_hash: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Author timestamp, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Commit {
+    // Commit messages can't contain NUL, so it's a safe delimiter; with `-z`,
+    // commits are terminated by NUL too.
+    const FORMAT: &'static str = "--pretty=tformat:%H%x00%h%x00%an%x00%ae%x00%at%x00%s%x00%b";
+    const FIELD_COUNT: usize = 7;
+
+    fn parse_all(command: &'static str, output: &str) -> Result<Vec<Self>, Error> {
+        let parse_failed = || Error::ParseFailed {
+            command,
+            output: output.to_owned(),
+        };
+        let fields = output
+            .strip_suffix('\0')
+            .filter(|fields| !fields.is_empty())
+            .map(|fields| fields.split('\0').collect::<Vec<_>>())
+            .unwrap_or_default();
+        let commits = fields.chunks_exact(Self::FIELD_COUNT);
+        if !commits.remainder().is_empty() {
+            return Err(parse_failed());
+        }
+        commits
+            .map(|fields| {
+                Ok(Self {
+                    hash: fields[0].to_owned(),
+                    short_hash: fields[1].to_owned(),
+                    author_name: fields[2].to_owned(),
+                    author_email: fields[3].to_owned(),
+                    timestamp: fields[4].parse().map_err(|_| parse_failed())?,
+                    subject: fields[5].to_owned(),
+                    body: fields[6].trim_end().to_owned(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlameLine {
+    /// The commit that last changed this line.
+    pub hash: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Author timestamp, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The line's number within that commit's version of the file.
+    pub original_line: u32,
+    /// The line's number within the blamed version of the file.
+    pub line: u32,
+    pub content: String,
+}
+
+impl BlameLine {
+    const COMMAND: &'static str = "blame --porcelain";
+
+    // Each line gets a `<hash> <original> <final> [<count>]` header, but the
+    // commit's details only follow the first time that commit appears.
+    fn parse_all(output: &str) -> Result<Vec<Self>, Error> {
+        #[derive(Clone, Default)]
+        struct Author {
+            name: String,
+            email: String,
+            timestamp: i64,
+        }
+        let parse_failed = || Error::ParseFailed {
+            command: Self::COMMAND,
+            output: output.to_owned(),
+        };
+        let mut authors = std::collections::HashMap::<&str, Author>::new();
+        let mut lines = Vec::new();
+        let mut current: Option<(&str, u32, u32)> = None;
+        for line in output.lines() {
+            if let Some(content) = line.strip_prefix('\t') {
+                let (hash, original_line, line) = current.take().ok_or_else(parse_failed)?;
+                let author = authors.get(hash).cloned().unwrap_or_default();
+                lines.push(Self {
+                    hash: hash.to_owned(),
+                    author_name: author.name,
+                    author_email: author.email,
+                    timestamp: author.timestamp,
+                    original_line,
+                    line,
+                    content: content.to_owned(),
+                });
+            } else if let Some((hash, _, _)) = current {
+                let author = authors.entry(hash).or_default();
+                let (key, value) = line.split_at(line.find(' ').unwrap_or(line.len()));
+                let value = value.trim_start();
+                match key {
+                    "author" => author.name = value.to_owned(),
+                    "author-mail" => {
+                        author.email = value
+                            .trim_start_matches('<')
+                            .trim_end_matches('>')
+                            .to_owned()
+                    }
+                    "author-time" => {
+                        author.timestamp = value.parse().map_err(|_| parse_failed())?
+                    }
+                    _ => (),
+                }
             } else {
-                Status::Fresh
+                let mut fields = line.split(' ');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(hash), Some(original_line), Some(line)) => {
+                        current = Some((
+                            hash,
+                            original_line.parse().map_err(|_| parse_failed())?,
+                            line.parse().map_err(|_| parse_failed())?,
+                        ));
+                    }
+                    _ => return Err(parse_failed()),
+                }
             }
+        }
+        Ok(lines)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GrepOptions {
+    /// Match the pattern literally instead of as a basic regex.
+    pub fixed_strings: bool,
+    pub ignore_case: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct GrepMatch {
+    pub path: PathBuf,
+    pub line_number: u32,
+    pub line: String,
+}
+
+impl GrepMatch {
+    const COMMAND: &'static str = "grep -n -z -I --no-color";
+
+    // With `-z`, each match is `<path>\0<line number>\0<line>\n`, where the
+    // path is prefixed with `<rev>:` when searching a revision.
+    fn parse_all(output: &[u8], rev: Option<&str>) -> Result<Vec<Self>, Error> {
+        let parse_failed = || Error::ParseFailed {
+            command: Self::COMMAND,
+            output: String::from_utf8_lossy(output).into_owned(),
         };
-        Ok(status)
+        let prefix = rev.map(|rev| format!("{}:", rev));
+        let mut matches = Vec::new();
+        let mut rest = output;
+        while !rest.is_empty() {
+            let mut take_until = |delimiter| {
+                let index = rest
+                    .iter()
+                    .position(|b| *b == delimiter)
+                    .ok_or_else(parse_failed)?;
+                let field = &rest[..index];
+                rest = &rest[index + 1..];
+                Ok(field)
+            };
+            let path = take_until(0)?;
+            let line_number = take_until(0)?;
+            let line = take_until(b'\n')?;
+            let path = match &prefix {
+                Some(prefix) => path
+                    .strip_prefix(prefix.as_bytes())
+                    .ok_or_else(parse_failed)?,
+                None => path,
+            };
+            matches.push(Self {
+                path: crate::path_from_bytes(path),
+                line_number: std::str::from_utf8(line_number)
+                    .ok()
+                    .and_then(|line_number| line_number.parse().ok())
+                    .ok_or_else(parse_failed)?,
+                line: String::from_utf8_lossy(line).into_owned(),
+            });
+        }
+        Ok(matches)
     }
+}
 
-    pub fn latest_commit(&self, format: impl AsRef<str>) -> Result<String, Error> {
-        self.git()
-            .command_parse(format!("log -1 --pretty={}", format.as_ref()))
-            .run_and_wait_for_str(|s| s.trim().to_owned())
-            .map_err(Error::LogFailed)
+#[derive(Clone, Debug)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    Renamed { from: PathBuf },
+    Copied { from: PathBuf },
+    TypeChanged,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+impl ChangedFile {
+    const COMMAND: &'static str = "diff --name-status";
+
+    // With `-z`, every status and path is terminated by NUL, so paths are
+    // passed through verbatim.
+    fn parse_all(output: &[u8]) -> Result<Vec<Self>, Error> {
+        let parse_failed = || Error::ParseFailed {
+            command: Self::COMMAND,
+            output: String::from_utf8_lossy(output).into_owned(),
+        };
+        let mut files = Vec::new();
+        let mut fields = output
+            .strip_suffix(b"\0")
+            .filter(|fields| !fields.is_empty())
+            .map(|fields| fields.split(|b| *b == 0))
+            .into_iter()
+            .flatten();
+        while let Some(status) = fields.next() {
+            let mut next_path = || {
+                fields
+                    .next()
+                    .map(crate::path_from_bytes)
+                    .ok_or_else(parse_failed)
+            };
+            // Renames and copies have a similarity score appended, i.e. `R100`.
+            let change = match status.first() {
+                Some(b'A') => Change::Added,
+                Some(b'M') => Change::Modified,
+                Some(b'D') => Change::Deleted,
+                Some(b'T') => Change::TypeChanged,
+                Some(b'R') => Change::Renamed { from: next_path()? },
+                Some(b'C') => Change::Copied { from: next_path()? },
+                _ => return Err(parse_failed()),
+            };
+            files.push(Self {
+                path: next_path()?,
+                change,
+            });
+        }
+        Ok(files)
     }
+}
 
-    pub fn latest_subject(&self) -> Result<String, Error> {
-        self.latest_commit("%s")
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiffStat {
+    pub files_changed: u32,
+    pub insertions: u32,
+    pub deletions: u32,
+    /// Binary files are counted in `files_changed`, but contribute no
+    /// insertions or deletions.
+    pub binary_files: u32,
+}
+
+impl DiffStat {
+    const COMMAND: &'static str = "diff --numstat";
+
+    fn parse(output: &str) -> Result<Self, Error> {
+        let parse_failed = || Error::ParseFailed {
+            command: Self::COMMAND,
+            output: output.to_owned(),
+        };
+        let mut stat = Self::default();
+        for line in output.lines() {
+            let mut counts = line.splitn(3, '\t');
+            match (counts.next(), counts.next(), counts.next()) {
+                (Some("-"), Some("-"), Some(_)) => stat.binary_files += 1,
+                (Some(insertions), Some(deletions), Some(_)) => {
+                    stat.insertions += insertions.parse::<u32>().map_err(|_| parse_failed())?;
+                    stat.deletions += deletions.parse::<u32>().map_err(|_| parse_failed())?;
+                }
+                _ => return Err(parse_failed()),
+            }
+            stat.files_changed += 1;
+        }
+        Ok(stat)
     }
+}
 
-    pub fn latest_body(&self) -> Result<String, Error> {
-        self.latest_commit("%b")
+#[derive(Clone, Debug)]
+pub struct FsckObject {
+    /// `commit`, `tree`, `blob`, or `tag`.
+    pub kind: String,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FsckReport {
+    /// Unreachable objects, which are harmless.
+    pub dangling: Vec<FsckObject>,
+    /// Objects that are referenced but don't exist.
+    pub missing: Vec<FsckObject>,
+    /// Everything git reported as an error, i.e. corrupt objects.
+    pub errors: Vec<String>,
+}
+
+impl FsckReport {
+    fn parse(stdout: &[u8], stderr: &[u8]) -> Self {
+        let mut report = Self::default();
+        for line in String::from_utf8_lossy(stdout).lines() {
+            let mut words = line.split_whitespace();
+            let list = match words.next() {
+                Some("dangling") => &mut report.dangling,
+                Some("missing") => &mut report.missing,
+                _ => continue,
+            };
+            if let (Some(kind), Some(hash)) = (words.next(), words.next()) {
+                list.push(FsckObject {
+                    kind: kind.to_owned(),
+                    hash: hash.to_owned(),
+                });
+            }
+        }
+        report.errors = String::from_utf8_lossy(stderr)
+            .lines()
+            .filter_map(|line| {
+                line.strip_prefix("error: ")
+                    .or_else(|| line.strip_prefix("fatal: "))
+            })
+            .map(ToOwned::to_owned)
+            .collect();
+        report
     }
 
-    pub fn update(&self, url: impl AsRef<std::ffi::OsStr>) -> Result<(), Error> {
-        let path = self.path();
-        if !path.is_dir() {
-            let parent = self
-                .path()
-                .parent()
-                .expect("developer error: `Repo` path was at root");
-            if !parent.is_dir() {
-                std::fs::create_dir_all(parent).map_err(|source| {
-                    Error::ParentDirCreationFailed {
-                        path: parent.to_owned(),
-                        source,
+    /// `true` if nothing is missing or corrupt.
+    pub fn is_healthy(&self) -> bool {
+        self.missing.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// What [`Repo::submodule_foreach`] got for each submodule, by name.
+pub type SubmoduleResults<T, E> = Vec<(String, Result<T, E>)>;
+
+#[derive(Clone, Debug)]
+pub struct Remote {
+    pub name: String,
+    pub fetch_url: String,
+    pub push_url: String,
+}
+
+impl Remote {
+    // `remote -v` lists each remote twice, as `<name>\t<url> (fetch)` and
+    // `<name>\t<url> (push)`.
+    fn parse_all(output: &str) -> Vec<Self> {
+        let mut remotes = Vec::<Self>::new();
+        for line in output.lines() {
+            let (name, rest) = match line.split_once('\t') {
+                Some(fields) => fields,
+                None => continue,
+            };
+            let (url, kind) = match rest.rsplit_once(' ') {
+                Some(fields) => fields,
+                None => continue,
+            };
+            let index = match remotes.iter().position(|remote| remote.name == name) {
+                Some(index) => index,
+                None => {
+                    remotes.push(Self {
+                        name: name.to_owned(),
+                        fetch_url: url.to_owned(),
+                        push_url: url.to_owned(),
+                    });
+                    remotes.len() - 1
+                }
+            };
+            match kind {
+                "(fetch)" => remotes[index].fetch_url = url.to_owned(),
+                "(push)" => remotes[index].push_url = url.to_owned(),
+                _ => (),
+            }
+        }
+        remotes
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StashEntry {
+    /// The entry's position in the stash, where `0` is the most recent.
+    pub index: usize,
+    pub message: String,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Worktree {
+    pub path: PathBuf,
+    /// `None` for bare repos.
+    pub head: Option<String>,
+    /// The checked out branch, or `None` if `HEAD` is detached.
+    pub branch: Option<String>,
+    pub bare: bool,
+    pub locked: bool,
+    /// The worktree's directory is gone, so `git worktree prune` would
+    /// remove it.
+    pub prunable: bool,
+}
+
+impl Worktree {
+    fn parse_all(output: &str) -> Vec<Self> {
+        let mut worktrees = Vec::new();
+        for record in output.split("\n\n") {
+            let mut worktree = Self::default();
+            let mut found = false;
+            for line in record.lines() {
+                let (key, value) = match line.find(' ') {
+                    Some(index) => (&line[..index], &line[index + 1..]),
+                    None => (line, ""),
+                };
+                match key {
+                    "worktree" => {
+                        worktree.path = value.into();
+                        found = true;
                     }
-                })?;
+                    "HEAD" => worktree.head = Some(value.to_owned()),
+                    "branch" => {
+                        let branch = value.strip_prefix("refs/heads/").unwrap_or(value);
+                        worktree.branch = Some(branch.to_owned());
+                    }
+                    "bare" => worktree.bare = true,
+                    "locked" => worktree.locked = true,
+                    "prunable" => worktree.prunable = true,
+                    _ => (),
+                }
+            }
+            if found {
+                worktrees.push(worktree);
             }
-            Git::new(parent)
-                .command_parse("clone --depth 1 --single-branch")
-                .with_arg(url)
-                .with_arg(path)
-                .run_and_wait()
-                .map_err(Error::CloneFailed)?;
-        } else {
-            println!(
-                "Updating `{}` repo...",
-                Path::new(
-                    self.path()
-                        .file_name()
-                        .expect("developer error: `Repo` path had no file name")
-                )
-                .display()
-            );
-            self.git()
-                .command_parse("fetch --depth 1")
-                .run_and_wait()
-                .map_err(Error::FetchFailed)?;
-            self.git()
-                .command_parse("reset --hard origin/master")
-                .run_and_wait()
-                .map_err(Error::ResetFailed)?;
-            self.git()
-                .command_parse("clean -dfx --exclude /target")
-                .run_and_wait()
-                .map_err(Error::CleanFailed)?;
         }
-        Ok(())
+        worktrees
+    }
+}
+
+/// Repos are equal if they have the same path, remote, and kind. Settings
+/// that only affect how commands are run, like timeouts and credentials,
+/// aren't compared.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Repo {
+    path: PathBuf,
+    #[cfg_attr(feature = "serde", serde(default = "default_remote"))]
+    remote: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    kind: RepoKind,
+    // The rest only matters while running commands, and we certainly don't
+    // want to write credentials anywhere.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    output: OutputMode,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    limits: Limits,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    interactive: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    env: Vec<(OsString, OsString)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    auth: Option<Auth>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_program"))]
+    program: PathBuf,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    backend: Option<Arc<dyn GitBackend>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    lock: LockPolicy,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    remove_stale_locks: bool,
+}
+
+impl PartialEq for Repo {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.path, &self.remote, self.kind) == (&other.path, &other.remote, other.kind)
+    }
+}
+
+impl Eq for Repo {}
+
+impl Hash for Repo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.remote.hash(state);
+        self.kind.hash(state);
+    }
+}
+
+/// i.e. "deps/my-sdk (origin)"
+impl Display for Repo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.path.display(), self.remote)
+    }
+}
+
+fn default_remote() -> String {
+    "origin".to_owned()
+}
+
+fn default_program() -> PathBuf {
+    "git".into()
+}
+
+impl Repo {
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            remote: default_remote(),
+            kind: Default::default(),
+            output: Default::default(),
+            limits: Default::default(),
+            interactive: false,
+            env: Vec::new(),
+            auth: None,
+            program: default_program(),
+            backend: None,
+            lock: Default::default(),
+            remove_stale_locks: false,
+        }
+    }
+
+    /// The repo that `start` is in, which can be anywhere in its working tree
+    /// (or in the repo itself, if it's bare), or `None` if `start` isn't in a
+    /// repo. In linked worktrees and submodules, this is the root of their own
+    /// working tree rather than the directory `.git` points to. Like git, this
+    /// fails from inside the `.git` directory of a repo with a working tree.
+    pub fn discover(start: impl AsRef<Path>) -> Result<Option<Self>, Error> {
+        let start = start.as_ref();
+        let git = Git::new(start);
+        let failed = |source| Error::DiscoverFailed {
+            start: start.to_owned(),
+            source,
+        };
+        let bare = match git
+            .command_parse("rev-parse --is-bare-repository")
+            .run_and_wait_for_str(|s| s.trim() == "true")
+        {
+            Ok(bare) => bare,
+            Err(err) if crate::not_a_repo(&err) => return Ok(None),
+            Err(err) => return Err(failed(err)),
+        };
+        let root = git
+            .command_parse(if bare {
+                "rev-parse --absolute-git-dir"
+            } else {
+                "rev-parse --show-toplevel"
+            })
+            .run_and_wait_for_str(|s| PathBuf::from(s.trim_end_matches('\n')))
+            .map_err(failed)?;
+        let kind = if !bare {
+            RepoKind::Checkout
+        } else if matches!(
+            git.config_get_bool("remote.origin.mirror", crate::config::Scope::Local),
+            Ok(Some(true))
+        ) {
+            RepoKind::Mirror
+        } else {
+            RepoKind::Bare
+        };
+        Ok(Some(Self::from_path(root).with_kind(kind)))
+    }
+
+    pub fn with_kind(mut self, kind: RepoKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> RepoKind {
+        self.kind
+    }
+
+    /// Use a remote other than `origin` for fetching and status checks.
+    pub fn with_remote(mut self, remote: impl Into<String>) -> Self {
+        self.remote = remote.into();
+        self
+    }
+
+    /// Whether git's own output, like clone and fetch progress, is shown.
+    /// It's captured by default.
+    pub fn with_output_mode(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        self.output
+    }
+
+    /// Give up on fetches, clones, and pulls that take longer than `timeout`,
+    /// failing with [`Error::TimedOut`]. Since git's stderr has to be watched
+    /// along the way, its messages are logged instead of being included in
+    /// other errors from those commands.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.limits.timeout
+    }
+
+    /// Retry fetches, clones, and pulls that fail in ways that look
+    /// transient, failing with [`Error::GaveUp`] if every attempt fails.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.limits.retry = retry;
+        self
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.limits.retry
+    }
+
+    /// What to do if another process is already updating or checking this
+    /// repo; see [`LockPolicy`].
+    pub fn with_lock_policy(mut self, lock: LockPolicy) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    pub fn lock_policy(&self) -> LockPolicy {
+        self.lock
+    }
+
+    /// Have [`Repo::update`] and [`Repo::status`] remove lock files (like
+    /// `index.lock`) left behind by a git process that crashed or was killed,
+    /// which otherwise make every command that needs them fail until they're
+    /// removed by hand. The same goes for a command killed by our timeout or
+    /// cancellation while we hold the lock. This only happens once we hold
+    /// our own lock, so they can't belong to another user of this crate, but
+    /// it's still only safe if nothing else runs git in the repo. Without
+    /// this, any lock files found are just logged.
+    pub fn with_stale_lock_removal(mut self, remove: bool) -> Self {
+        self.remove_stale_locks = remove;
+        self
+    }
+
+    pub fn stale_lock_removal(&self) -> bool {
+        self.remove_stale_locks
+    }
+
+    /// Let git prompt for credentials; see [`Git::with_interactive`].
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// Environment variables to set for every command; see [`Git::with_env`].
+    pub fn with_env(mut self, vars: &[(OsString, OsString)]) -> Self {
+        self.env = vars.to_vec();
+        self
+    }
+
+    pub fn env(&self) -> &[(OsString, OsString)] {
+        &self.env
+    }
+
+    /// Credentials for the HTTP(S) remote at [`Auth::url`], used by
+    /// [`Repo::update`], [`Repo::verify_remote`], and everything else that
+    /// talks to the remote.
+    /// Unlike credentials in the URL, these never end up in `.git/config`.
+    pub fn with_auth(mut self, auth: impl Into<Option<Auth>>) -> Self {
+        self.auth = auth.into();
+        self
+    }
+
+    pub fn auth(&self) -> Option<&Auth> {
+        self.auth.as_ref()
+    }
+
+    /// The git executable to run; see [`Git::with_program`].
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// Where commands come from; see [`Git::with_backend`]. Clones of the
+    /// repo share the backend.
+    pub fn with_backend(mut self, backend: impl Into<Option<Arc<dyn GitBackend>>>) -> Self {
+        self.backend = backend.into();
+        self
+    }
+
+    pub fn backend(&self) -> Option<&Arc<dyn GitBackend>> {
+        self.backend.as_ref()
+    }
+
+    /// Takes on the settings `git` passes on to the commands it runs.
+    pub(crate) fn with_settings_of(self, git: Git<'_>) -> Self {
+        let repo = self
+            .with_output_mode(git.output_mode())
+            .with_retry_policy(git.retry_policy())
+            .with_interactive(git.interactive())
+            .with_env(git.env())
+            .with_auth(git.auth().cloned())
+            .with_program(git.program())
+            .with_backend(git.backend().cloned());
+        match git.cancellation() {
+            Some(token) => repo.with_cancellation(token),
+            None => repo,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn remote(&self) -> &str {
+        &self.remote
+    }
+
+    pub fn git(&self) -> Git<'_> {
+        Git::new(self.path())
+            .with_program(&self.program)
+            .with_backend(self.backend.as_ref())
+            .with_output_mode(self.output)
+            .with_retry_policy(self.limits.retry)
+            .with_interactive(self.interactive)
+            .with_env(&self.env)
+            .with_auth(self.auth.as_ref())
+            .with_cancellation(self.limits.cancel.as_ref())
+    }
+
+    /// Takes our lock on the repo, and then deals with any stale git locks.
+    fn lock(&self) -> Result<Option<RepoLock>, Error> {
+        let lock = RepoLock::acquire(self.path(), self.lock)?;
+        if self.remove_stale_locks && lock.is_some() {
+            self.remove_stale_locks();
+        } else if let Ok(Some(git_dir)) = self.git().dir_if_repo(Git::git_dir) {
+            let index_lock = git_dir.join("index.lock");
+            if index_lock.exists() {
+                log::warn!(
+                    "{:?} exists, so commands that change the index will fail; if git isn't running there, it was left behind by a git that crashed, and can be removed",
+                    index_lock
+                );
+            }
+        }
+        Ok(lock)
+    }
+
+    pub fn status(&self) -> Result<Status, Error> {
+        let _lock = if self.path().exists() {
+            self.lock()?
+        } else {
+            None
+        };
+        if self.path().is_dir() && !self.kind().has_working_tree() {
+            return self.status_bare();
+        }
+        if self.path().is_dir() {
+            self.fetch(&Default::default())?;
+        }
+        self.status_local()
+    }
+
+    /// Fetches according to `options`, returning the hash of the first
+    /// fetched head (if anything was fetched).
+    pub fn fetch(&self, options: &FetchOptions) -> Result<Option<String>, Error> {
+        let mut command = self.fetch_command(options);
+        let result = self.run_remote(&mut command, None);
+        self.fetched(options, result)
+    }
+
+    fn fetch_command(&self, options: &FetchOptions) -> bossy::Command {
+        let remote = options.remote.as_deref().unwrap_or_else(|| self.remote());
+        let mut command = self.git().command();
+        command.add_arg("fetch");
+        if options.prune {
+            command.add_arg("--prune");
+        }
+        if options.tags {
+            command.add_arg("--tags");
+        }
+        if let Some(depth) = options.depth {
+            command.add_arg("--depth").add_arg(depth.to_string());
+        }
+        if options.unshallow {
+            command.add_arg("--unshallow");
+        }
+        command.add_arg(remote).add_args(&options.refspecs);
+        command
+    }
+
+    /// Checks how a fetch according to `options` went, returning the hash of
+    /// the first fetched head.
+    fn fetched(
+        &self,
+        options: &FetchOptions,
+        result: Result<bossy::Result<()>, Error>,
+    ) -> Result<Option<String>, Error> {
+        let remote = options.remote.as_deref().unwrap_or_else(|| self.remote());
+        result?.map_err(|source| {
+            let remote = remote.to_owned();
+            if options.refspecs.is_empty() {
+                Error::FetchFailed { remote, source }
+            } else {
+                Error::FetchRefspecsFailed {
+                    remote,
+                    refspecs: options.refspecs.clone(),
+                    source,
+                }
+            }
+        })?;
+        Ok(self.rev_parse_hash("rev-parse -q --verify FETCH_HEAD").ok())
+    }
+
+    /// Bare repos don't have remote-tracking branches to compare against, so
+    /// we ask the remote for its `HEAD` instead.
+    fn status_bare(&self) -> Result<Status, Error> {
+        let remote_head = self.remote_head()?;
+        if !self.contains_commit(&remote_head)? {
+            let result = self.run_remote(&mut self.fetch_commit_command(&remote_head), None);
+            self.fetched_from_remote(result)?;
+        }
+        self.status_against(&remote_head)
+    }
+
+    /// The commit our remote's `HEAD` points to.
+    fn remote_head(&self) -> Result<String, Error> {
+        let remote = self.remote();
+        self.git()
+            .command()
+            .with_args(["ls-remote", remote, "HEAD"])
+            .run_and_wait_for_str(|s| {
+                s.lines()
+                    .find_map(|line| line.strip_suffix("\tHEAD"))
+                    .map(ToOwned::to_owned)
+            })
+            .map_err(|source| Error::LsRemoteFailed {
+                remote: remote.to_owned(),
+                source,
+            })?
+            .ok_or_else(|| Error::DefaultBranchUnresolved {
+                remote: remote.to_owned(),
+            })
+    }
+
+    /// Fetching by hash downloads the objects we need to count commits
+    /// without touching any refs.
+    fn fetch_commit_command(&self, hash: &str) -> bossy::Command {
+        self.git()
+            .command()
+            .with_args(["fetch", self.remote(), hash])
+    }
+
+    fn status_against(&self, remote_head: &str) -> Result<Status, Error> {
+        self.ahead_behind(remote_head)
+            .map(|(ahead, behind)| Status::from_counts(ahead, behind))
+    }
+
+    /// The same as [`Repo::status`], but compares against the upstream as of
+    /// the last fetch rather than fetching first. Bare and mirror repos have
+    /// no upstream to compare against, so this fails with
+    /// [`Error::NoUpstream`] for them.
+    pub fn status_local(&self) -> Result<Status, Error> {
+        if !self.path().is_dir() {
+            Ok(Status::Missing)
+        } else if !self.kind().has_working_tree() {
+            Err(Error::NoUpstream)
+        } else {
+            let base = self.upstream_base()?;
+            self.ahead_behind(&base)
+                .map(|(ahead, behind)| Status::from_counts(ahead, behind))
+        }
+    }
+
+    /// The upstream of the checked out branch, abbreviated (i.e.
+    /// `origin/main`). This is `None` if nothing's tracked or `HEAD` is
+    /// detached.
+    pub fn upstream(&self) -> Result<Option<String>, Error> {
+        match self
+            .git()
+            .command_parse("rev-parse --abbrev-ref @{u}")
+            .run_and_wait_for_string()
+        {
+            Ok(upstream) => Ok(Some(upstream.trim().to_owned())),
+            Err(err) if no_upstream(&err) => Ok(None),
+            Err(err) => Err(Error::UpstreamCheckFailed(err)),
+        }
+    }
+
+    /// Sets the checked out branch to track `branch` on `remote`. The
+    /// remote-tracking branch needs to exist, so fetch first if it doesn't.
+    pub fn set_upstream(&self, remote: &str, branch: &str) -> Result<(), Error> {
+        self.git()
+            .command()
+            .with_arg("branch")
+            .with_arg(format!("--set-upstream-to={}/{}", remote, branch))
+            .run_and_wait_for_output()
+            .map_err(|source| Error::UpstreamSetFailed {
+                remote: remote.to_owned(),
+                branch: branch.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// The remote-tracking ref that `HEAD` is compared against. This is
+    /// usually just the upstream, but if the upstream's on a different remote
+    /// than ours, then we use the same branch name on our remote instead.
+    fn upstream_base(&self) -> Result<String, Error> {
+        #[cfg(feature = "gix")]
+        if let Some(base) = crate::native::open(self.git())
+            .and_then(|repo| crate::native::upstream_base(&repo, self.remote()))
+        {
+            return Ok(base);
+        }
+        if self.upstream()?.is_none() {
+            return Err(Error::NoUpstream);
+        }
+        self.git()
+            .command_parse("for-each-ref --format=%(HEAD)%00%(upstream:remoteref) refs/heads")
+            .run_and_wait_for_str(|s| {
+                s.lines()
+                    .filter_map(|line| line.strip_prefix("*\0refs/heads/"))
+                    .find(|branch| !branch.is_empty())
+                    .map(|branch| format!("refs/remotes/{}/{}", self.remote(), branch))
+            })
+            .map_err(Error::UpstreamCheckFailed)?
+            .ok_or(Error::NoUpstream)
+    }
+
+    /// How many commits `HEAD` has that `base` doesn't, and vice versa.
+    pub fn ahead_behind(&self, base: &str) -> Result<(u32, u32), Error> {
+        const COMMAND: &str = "rev-list --left-right --count";
+        #[cfg(feature = "gix")]
+        if let Some(counts) = crate::native::open(self.git())
+            .and_then(|repo| crate::native::ahead_behind(&repo, base))
+        {
+            return Ok(counts);
+        }
+        self.verify_commit(base)?;
+        let output = self
+            .git()
+            .command_parse(COMMAND)
+            .with_arg(format!("HEAD...{}", base))
+            .run_and_wait_for_string()
+            .map_err(Error::RevListFailed)?;
+        let mut counts = output.trim().split('\t').map(str::parse::<u32>);
+        match (counts.next(), counts.next(), counts.next()) {
+            (Some(Ok(ahead)), Some(Ok(behind)), None) => Ok((ahead, behind)),
+            _ => Err(Error::ParseFailed {
+                command: COMMAND,
+                output,
+            }),
+        }
+    }
+
+    /// How many commits are reachable from `rev` (or `HEAD`). Since shallow
+    /// clones don't have the full history, this fails with
+    /// [`Error::CountTruncated`] for them.
+    pub fn commit_count(&self, rev: Option<&str>) -> Result<u64, Error> {
+        const COMMAND: &str = "rev-list --count";
+        let rev = rev.unwrap_or("HEAD");
+        let hash = self.verify_commit(rev)?;
+        let output = self
+            .git()
+            .command_parse(COMMAND)
+            .with_arg(hash)
+            .run_and_wait_for_string()
+            .map_err(Error::RevListFailed)?;
+        let count = output.trim().parse().map_err(|_| Error::ParseFailed {
+            command: COMMAND,
+            output: output.clone(),
+        })?;
+        if self.is_shallow()? {
+            Err(Error::CountTruncated {
+                rev: rev.to_owned(),
+                count,
+            })
+        } else {
+            Ok(count)
+        }
+    }
+
+    /// Paths with uncommitted changes, including untracked files.
+    pub fn dirty_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let output = self
+            .git()
+            .command_parse("status --porcelain -z")
+            .run_and_wait_for_output()
+            .map_err(Error::StatusFailed)?;
+        let mut paths = Vec::new();
+        let mut entries = output.stdout().split(|b| *b == 0);
+        while let Some(entry) = entries.next() {
+            if entry.len() < 4 {
+                continue;
+            }
+            let (status, path) = entry.split_at(3);
+            paths.push(crate::path_from_bytes(path));
+            // Renames and copies are followed by their original path.
+            if matches!(status[0], b'R' | b'C') {
+                entries.next();
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Files that differ between the revisions `from` and `to`.
+    pub fn changed_files(&self, from: &str, to: &str) -> Result<Vec<ChangedFile>, Error> {
+        let range = format!("{}..{}", from, to);
+        let output = self
+            .git()
+            .command_parse(ChangedFile::COMMAND)
+            .with_args(["-z", "-M", &range, "--"])
+            .run_and_wait_for_output()
+            .map_err(|source| Error::DiffFailed { range, source })?;
+        ChangedFile::parse_all(output.stdout())
+    }
+
+    /// Summarizes the changes between the revisions `from` and `to`.
+    pub fn diff_stat(&self, from: &str, to: &str) -> Result<DiffStat, Error> {
+        let range = format!("{}..{}", from, to);
+        let output = self
+            .git()
+            .command_parse(DiffStat::COMMAND)
+            .with_args(["-M", &range, "--"])
+            .run_and_wait_for_string()
+            .map_err(|source| Error::DiffFailed { range, source })?;
+        DiffStat::parse(&output)
+    }
+
+    /// Tracked files matching `pathspec`, relative to the repo's path. If
+    /// `include_untracked` is set, untracked files that aren't ignored are
+    /// included as well.
+    pub fn ls_files(
+        &self,
+        pathspec: Option<&str>,
+        include_untracked: bool,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let mut command = self.git().command_parse("ls-files -z --cached");
+        if include_untracked {
+            command.add_args(["--others", "--exclude-standard"]);
+        }
+        command.add_arg("--");
+        if let Some(pathspec) = pathspec {
+            command.add_arg(pathspec);
+        }
+        let output = command
+            .run_and_wait_for_output()
+            .map_err(Error::LsFilesFailed)?;
+        Ok(output
+            .stdout()
+            .split(|b| *b == 0)
+            .filter(|path| !path.is_empty())
+            .map(crate::path_from_bytes)
+            .collect())
+    }
+
+    /// Lines in tracked files matching `pattern`, either at `rev` or in the
+    /// working tree. Binary files are skipped.
+    pub fn grep(
+        &self,
+        pattern: &str,
+        rev: Option<&str>,
+        options: &GrepOptions,
+    ) -> Result<Vec<GrepMatch>, Error> {
+        let mut command = self.git().command_parse(GrepMatch::COMMAND);
+        if options.fixed_strings {
+            command.add_arg("--fixed-strings");
+        }
+        if options.ignore_case {
+            command.add_arg("--ignore-case");
+        }
+        command.add_arg("-e").add_arg(pattern);
+        if let Some(rev) = rev {
+            command.add_arg(rev);
+        }
+        match command.with_arg("--").run_and_wait_for_output() {
+            Ok(output) => GrepMatch::parse_all(output.stdout(), rev),
+            // Exit code 1 means there weren't any matches.
+            Err(err) if err.code() == Some(1) => Ok(Vec::new()),
+            Err(source) => Err(Error::GrepFailed {
+                pattern: pattern.to_owned(),
+                source,
+            }),
+        }
+    }
+
+    /// Whether each of `paths` is ignored, in the same order as `paths`.
+    pub fn check_ignore(&self, paths: &[&Path]) -> Result<Vec<bool>, Error> {
+        // All of stdin is written before stdout is read, so batches are kept
+        // small enough to fit in the pipe buffer; otherwise git could block
+        // on a full stdout while we block on a full stdin.
+        const BATCH_BYTES: usize = 4096;
+        let mut ignored = Vec::with_capacity(paths.len());
+        let mut rest = paths;
+        while !rest.is_empty() {
+            let mut input = Vec::new();
+            let mut count = 0;
+            for path in rest {
+                let path = crate::path_to_bytes(path);
+                if count > 0 && input.len() + path.len() + 1 > BATCH_BYTES {
+                    break;
+                }
+                input.extend_from_slice(&path);
+                input.push(0);
+                count += 1;
+            }
+            ignored.extend(self.check_ignore_batch(&input, count)?);
+            rest = &rest[count..];
+        }
+        Ok(ignored)
+    }
+
+    fn check_ignore_batch(&self, input: &[u8], count: usize) -> Result<Vec<bool>, Error> {
+        let command = "check-ignore --stdin -z -v --non-matching";
+        let mut handle = self
+            .git()
+            .command_parse(command)
+            .with_stdin_piped()
+            .with_stdout_piped()
+            .with_stderr_piped()
+            .run()
+            .map_err(Error::CheckIgnoreFailed)?;
+        if let Some(stdin) = handle.stdin() {
+            // If git exits early, the error is reported when waiting.
+            let _ = stdin.write_all(input);
+        }
+        let output = match handle.wait_for_output() {
+            Ok(output) => output,
+            // Exit code 1 just means that none of the paths are ignored.
+            Err(err) if err.code() == Some(1) => return Ok(vec![false; count]),
+            Err(err) => return Err(Error::CheckIgnoreFailed(err)),
+        };
+        // Each path gets a record of source, line number, pattern, and path,
+        // where the first three are empty if no pattern matched.
+        let fields = output
+            .stdout()
+            .strip_suffix(b"\0")
+            .map(|fields| fields.split(|b| *b == 0).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let records = fields.chunks_exact(4);
+        if !records.remainder().is_empty() || records.len() != count {
+            return Err(Error::ParseFailed {
+                command,
+                output: String::from_utf8_lossy(output.stdout()).into_owned(),
+            });
+        }
+        // A matching negated pattern means the path is explicitly included.
+        Ok(records
+            .map(|record| !record[0].is_empty() && !record[2].starts_with(b"!"))
+            .collect())
+    }
+
+    pub fn is_dirty(&self) -> Result<bool, Error> {
+        self.dirty_paths().map(|paths| !paths.is_empty())
+    }
+
+    /// The checked out branch, or `None` if `HEAD` is detached.
+    pub fn current_branch(&self) -> Result<Option<String>, Error> {
+        match self
+            .git()
+            .command_parse("symbolic-ref --short -q HEAD")
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+        {
+            Ok(branch) => Ok(Some(branch)),
+            // `-q` makes a detached `HEAD` exit with 1 instead of erroring.
+            Err(err) if err.code() == Some(1) => Ok(None),
+            Err(err) => Err(Error::CurrentBranchFailed(err)),
+        }
+    }
+
+    pub fn is_detached(&self) -> Result<bool, Error> {
+        self.current_branch().map(|branch| branch.is_none())
+    }
+
+    /// Short names of refs under `prefix`, skipping symbolic refs like
+    /// `origin/HEAD`.
+    fn refs(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        self.git()
+            .command_parse("for-each-ref --format=%(refname)%00%(symref)")
+            .with_arg(prefix)
+            .run_and_wait_for_str(|s| {
+                s.lines()
+                    .filter_map(|line| line.strip_suffix('\0'))
+                    .filter_map(|name| name.strip_prefix(prefix))
+                    .map(ToOwned::to_owned)
+                    .collect()
+            })
+            .map_err(Error::RefListFailed)
+    }
+
+    /// Local branches.
+    pub fn branches(&self) -> Result<Vec<String>, Error> {
+        self.refs("refs/heads/")
+    }
+
+    /// Remote-tracking branches, including the remote name, i.e. `origin/main`.
+    pub fn remote_branches(&self) -> Result<Vec<String>, Error> {
+        self.refs("refs/remotes/")
+    }
+
+    /// Whether a local branch named `name` exists.
+    pub fn branch_exists(&self, name: &str) -> Result<bool, Error> {
+        match self
+            .git()
+            .command_parse("show-ref --verify --quiet")
+            .with_arg(format!("refs/heads/{}", name))
+            .run_and_wait_for_output()
+        {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == Some(1) => Ok(false),
+            Err(err) => Err(Error::RefListFailed(err)),
+        }
+    }
+
+    /// Creates a branch at `start_point`, or at `HEAD` if that's `None`,
+    /// without checking it out.
+    pub fn create_branch(&self, name: &str, start_point: Option<&str>) -> Result<(), Error> {
+        self.git()
+            .command()
+            .with_arg("branch")
+            .with_arg(name)
+            .with_args(start_point)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::BranchCreateFailed {
+                branch: name.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// Switches to the local branch `name`, creating it at `HEAD` first if
+    /// `create` is set. If uncommitted changes would be overwritten, this
+    /// fails with [`Error::WouldOverwrite`] and leaves everything as is.
+    pub fn switch(&self, name: &str, create: bool) -> Result<(), Error> {
+        let mut command = self.git().command();
+        // `git switch` only exists as of git 2.23.
+        if self.git().ensure_version(GitVersion::new(2, 23, 0)).is_ok() {
+            command.add_arg("switch");
+            if create {
+                command.add_arg("-c");
+            }
+            command.add_arg(name);
+        } else if create {
+            command.add_args(["checkout", "-b"]).add_arg(name);
+        } else {
+            // The trailing `--` keeps `name` from being taken as a path.
+            command.add_arg("checkout").add_arg(name).add_arg("--");
+        }
+        command.run_and_wait_for_output().map_err(|source| {
+            let paths = overwritten_paths(&source);
+            if paths.is_empty() {
+                Error::SwitchFailed {
+                    branch: name.to_owned(),
+                    source,
+                }
+            } else {
+                Error::WouldOverwrite {
+                    branch: name.to_owned(),
+                    paths,
+                }
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Deletes the local branch `name`. Unless `force` is set, git refuses to
+    /// delete branches that haven't been merged.
+    pub fn delete_branch(&self, name: &str, force: bool) -> Result<(), Error> {
+        self.git()
+            .command()
+            .with_arg("branch")
+            .with_arg(if force { "-D" } else { "-d" })
+            .with_arg(name)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::BranchDeleteFailed {
+                branch: name.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub fn tags(&self) -> Result<Vec<String>, Error> {
+        self.refs("refs/tags/")
+    }
+
+    /// Creates a lightweight tag pointing at `rev`, or `HEAD` if unspecified.
+    /// Unless `force` is set, this fails if the tag already exists.
+    pub fn create_tag(&self, name: &str, rev: Option<&str>, force: bool) -> Result<(), Error> {
+        let mut command = self.git().command();
+        command.add_arg("tag");
+        if force {
+            command.add_arg("--force");
+        }
+        command.add_arg(name);
+        if let Some(rev) = rev {
+            command.add_arg(rev);
+        }
+        command
+            .run_and_wait_for_output()
+            .map_err(|source| Error::TagCreateFailed {
+                name: name.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub fn delete_tag(&self, name: &str) -> Result<(), Error> {
+        self.git()
+            .command_parse("tag --delete")
+            .with_arg(name)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::TagDeleteFailed {
+                name: name.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// The most recent tag reachable from `HEAD`, with a `-N-gHASH` suffix if
+    /// `HEAD` isn't tagged itself. This falls back to the short hash if there
+    /// aren't any reachable tags, unless the clone is shallow, in which case
+    /// this fails with [`Error::DescribeShallow`].
+    pub fn describe(&self) -> Result<String, Error> {
+        match self
+            .git()
+            .command_parse("describe --tags")
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+        {
+            Ok(description) => Ok(description),
+            Err(err) => {
+                let no_tags = err
+                    .stderr()
+                    .map(String::from_utf8_lossy)
+                    .filter(|stderr| {
+                        stderr.contains("No names found") || stderr.contains("No tags can describe")
+                    })
+                    .is_some();
+                if !no_tags {
+                    Err(Error::DescribeFailed(err))
+                } else if self.is_shallow()? {
+                    Err(Error::DescribeShallow)
+                } else {
+                    self.head_short_hash()
+                }
+            }
+        }
+    }
+
+    /// Exports the tree at `rev` to `dest` without any git metadata. If
+    /// `prefix` is given, every path is placed under that directory.
+    pub fn archive(
+        &self,
+        rev: &str,
+        dest: &Path,
+        format: ArchiveFormat,
+        prefix: Option<&str>,
+    ) -> Result<(), Error> {
+        let dest = std::path::absolute(dest).map_err(|source| Error::ArchiveDestFailed {
+            path: dest.to_owned(),
+            source,
+        })?;
+        let output = match format {
+            ArchiveFormat::Dir => {
+                std::fs::create_dir_all(&dest).map_err(|source| Error::ArchiveDestFailed {
+                    path: dest.clone(),
+                    source,
+                })?;
+                dest.join(format!(".hit-archive-{}.tar", std::process::id()))
+            }
+            _ => dest.clone(),
+        };
+        let mut command = self.git().command();
+        command
+            .add_arg("archive")
+            .add_arg(format!("--format={}", format.git_format()));
+        if let Some(prefix) = prefix {
+            let separator = if prefix.ends_with('/') { "" } else { "/" };
+            command.add_arg(format!("--prefix={}{}", prefix, separator));
+        }
+        command
+            .with_arg("-o")
+            .with_arg(&output)
+            .with_args(["--", rev])
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::ArchiveFailed {
+                rev: rev.to_owned(),
+                source,
+            })?;
+        if let ArchiveFormat::Dir = format {
+            let result = bossy::Command::impure("tar")
+                .with_arg("-xf")
+                .with_arg(&output)
+                .with_arg("-C")
+                .with_arg(&dest)
+                .run_and_wait_in(self.output);
+            if let Err(err) = std::fs::remove_file(&output) {
+                log::warn!("failed to remove temporary archive {:?}: {}", output, err);
+            }
+            result.map_err(|source| Error::ArchiveExtractFailed { dest, source })?;
+        }
+        Ok(())
+    }
+
+    fn rev_parse_hash(&self, command: &'static str) -> Result<String, Error> {
+        let output = self
+            .git()
+            .command_parse(command)
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+            .map_err(Error::RevParseLocalFailed)?;
+        if !output.is_empty() && output.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(output)
+        } else {
+            Err(Error::ParseFailed { command, output })
+        }
+    }
+
+    pub fn head_hash(&self) -> Result<String, Error> {
+        #[cfg(feature = "gix")]
+        if let Some(hash) =
+            crate::native::open(self.git()).and_then(|repo| crate::native::head_hash(&repo))
+        {
+            return Ok(hash);
+        }
+        self.rev_parse_hash("rev-parse HEAD")
+    }
+
+    pub fn head_short_hash(&self) -> Result<String, Error> {
+        self.rev_parse_hash("rev-parse --short HEAD")
+    }
+
+    /// Whether `rev` resolves to a commit that's present locally, which
+    /// isn't a given in shallow clones.
+    pub fn contains_commit(&self, rev: &str) -> Result<bool, Error> {
+        match self
+            .git()
+            .command_parse("cat-file -e")
+            .with_arg(format!("{}^{{commit}}", rev))
+            .run_and_wait_for_output()
+        {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == Some(1) => Ok(false),
+            // Revisions that don't resolve at all fail with 128 instead.
+            Err(err)
+                if err
+                    .stderr()
+                    .map(String::from_utf8_lossy)
+                    .filter(|stderr| stderr.contains("Not a valid object name"))
+                    .is_some() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(Error::CommitCheckFailed {
+                rev: rev.to_owned(),
+                source: err,
+            }),
+        }
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`. Every commit is its
+    /// own ancestor.
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool, Error> {
+        match self
+            .git()
+            .command_parse("merge-base --is-ancestor")
+            .with_args([ancestor, descendant])
+            .run_and_wait_for_output()
+        {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == Some(1) => Ok(false),
+            Err(err) => Err(Error::AncestryCheckFailed {
+                ancestor: ancestor.to_owned(),
+                descendant: descendant.to_owned(),
+                source: err,
+            }),
+        }
+    }
+
+    /// Resolves `rev` to a commit hash, failing with
+    /// [`Error::RevisionNotFound`] if there's no such commit.
+    fn verify_commit(&self, rev: &str) -> Result<String, Error> {
+        self.git()
+            .command_parse("rev-parse -q --verify")
+            .with_arg(format!("{}^{{commit}}", rev))
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+            .map_err(|_| Error::RevisionNotFound {
+                rev: rev.to_owned(),
+            })
+    }
+
+    /// Moves the current branch (or detached `HEAD`) to `rev`.
+    pub fn reset(&self, rev: &str, mode: ResetMode) -> Result<(), Error> {
+        let hash = self.verify_commit(rev)?;
+        self.git()
+            .command_parse("reset -q")
+            .with_arg(mode.flag())
+            .with_arg(hash)
+            .run_and_wait_in(self.output)
+            .map_err(Error::ResetFailed)?;
+        Ok(())
+    }
+
+    pub fn is_shallow(&self) -> Result<bool, Error> {
+        self.git()
+            .command_parse("rev-parse --is-shallow-repository")
+            .run_and_wait_for_str(|s| s.trim() == "true")
+            .map_err(Error::ShallowCheckFailed)
+    }
+
+    /// Pushes `refspec` (or the current branch's configured push target) to
+    /// `remote`. Rejections fail with [`Error::PushRejected`], while problems
+    /// reaching the remote fail with the same errors as
+    /// [`Repo::verify_remote`]. This is subject to our timeout and retry
+    /// policy like fetches are, though git's output can't be kept when
+    /// there's a timeout or cancellation token, so then rejections just fail
+    /// with [`Error::PushFailed`].
+    pub fn push(
+        &self,
+        remote: &str,
+        refspec: Option<&str>,
+        options: &PushOptions,
+    ) -> Result<(), Error> {
+        let mut command = self.git().command_parse("push --porcelain");
+        if options.set_upstream {
+            command.add_arg("--set-upstream");
+        }
+        if options.force_with_lease {
+            command.add_arg("--force-with-lease");
+        }
+        if options.tags {
+            command.add_arg("--tags");
+        }
+        command.add_arg(remote);
+        if let Some(refspec) = refspec {
+            command.add_arg(refspec);
+        }
+        // The rejections are in the output, so we always capture it.
+        self.run_remote_in(&mut command, OutputMode::Capture, None)?
+            .map_err(|source| {
+                // With `--porcelain`, rejected refs are flagged with `!`.
+                let rejections = source
+                    .stdout()
+                    .map(String::from_utf8_lossy)
+                    .map(|stdout| {
+                        stdout
+                            .lines()
+                            .filter_map(|line| line.strip_prefix("!\t"))
+                            .map(|line| line.replace('\t', " "))
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                if !rejections.is_empty() {
+                    return Error::PushRejected {
+                        remote: remote.to_owned(),
+                        rejections,
+                    };
+                }
+                RemoteFailure::error(remote.into(), source, |_, source| Error::PushFailed {
+                    remote: remote.to_owned(),
+                    source,
+                })
+            })?;
+        Ok(())
+    }
+
+    /// Fetches the complete history of a shallow clone. Does nothing if the
+    /// clone is already complete.
+    pub fn unshallow(&self) -> Result<(), Error> {
+        if self.is_shallow()? {
+            self.run_remote(
+                &mut self
+                    .git()
+                    .command()
+                    .with_args(["fetch", "--unshallow", self.remote()]),
+                None,
+            )?
+            .map_err(|source| Error::UnshallowFailed {
+                remote: self.remote().to_owned(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if `rev` was fetched directly into `FETCH_HEAD`.
+    fn fetch_rev(&self, rev: &str) -> Result<bool, Error> {
+        let fetch_failed = |source| Error::FetchFailed {
+            remote: self.remote().to_owned(),
+            source,
+        };
+        let shallow = self.is_shallow()?;
+        let mut command = self.git().command();
+        command.add_arg("fetch");
+        if shallow {
+            command.add_args(["--depth", "1"]);
+        }
+        command.add_args([self.remote(), rev]);
+        let result = self.run_remote(&mut command, None)?;
+        let fetched_directly = result.is_ok();
+        if let Err(err) = result {
+            // Not every server lets you fetch arbitrary commits, so we fall
+            // back to fetching everything.
+            log::info!(
+                "failed to fetch {:?} directly, so fetching all history: {}",
+                rev,
+                err
+            );
+            let mut command = self.git().command();
+            command.add_arg("fetch");
+            if shallow {
+                command.add_arg("--unshallow");
+            }
+            command.add_args(["--tags", self.remote()]);
+            self.run_remote(&mut command, None)?.map_err(fetch_failed)?;
+        }
+        Ok(fetched_directly)
+    }
+
+    /// Checks out `rev` as a detached `HEAD`, fetching it first if it isn't
+    /// present locally.
+    pub fn checkout(&self, rev: &str) -> Result<(), Error> {
+        let target = if self.contains_commit(rev)? {
+            rev
+        } else {
+            let fetched_directly = self.fetch_rev(rev)?;
+            // Fetching a tag or branch name directly only updates `FETCH_HEAD`.
+            if fetched_directly && !self.contains_commit(rev)? {
+                "FETCH_HEAD"
+            } else {
+                rev
+            }
+        };
+        self.git()
+            .command_parse("checkout --detach")
+            .with_arg(target)
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::CheckoutFailed {
+                rev: rev.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub fn latest_commit(&self, format: impl AsRef<str>) -> Result<String, Error> {
+        self.git()
+            .command_parse("log -1")
+            .with_arg(format!("--pretty={}", format.as_ref()))
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+            .map_err(Error::LogFailed)
+    }
+
+    /// Gets all the metadata for `HEAD` in one go.
+    pub fn head_commit(&self) -> Result<Commit, Error> {
+        const COMMAND: &str = "log -1 -z";
+        let output = self
+            .git()
+            .command_parse(COMMAND)
+            .with_arg(Commit::FORMAT)
+            .run_and_wait_for_string()
+            .map_err(Error::LogFailed)?;
+        Commit::parse_all(COMMAND, &output)?
+            .pop()
+            .ok_or(Error::ParseFailed {
+                command: COMMAND,
+                output,
+            })
+    }
+
+    /// Commits reachable from `HEAD` (or within `range`, i.e. `"abc..def"`),
+    /// newest first. A repo without any commits yields an empty list.
+    pub fn log(&self, range: Option<&str>, limit: Option<usize>) -> Result<Vec<Commit>, Error> {
+        self.log_with_args(
+            limit,
+            range.is_none(),
+            range.into_iter().map(std::ffi::OsStr::new),
+        )
+    }
+
+    /// Commits reachable from `HEAD` that touched `path`, newest first. If
+    /// `follow` is set, history is continued across renames.
+    pub fn file_log(
+        &self,
+        path: &Path,
+        limit: Option<usize>,
+        follow: bool,
+    ) -> Result<Vec<Commit>, Error> {
+        let follow = if follow { Some("--follow") } else { None };
+        let args = follow
+            .into_iter()
+            .chain(Some("--"))
+            .map(std::ffi::OsStr::new)
+            .chain(Some(path.as_os_str()));
+        self.log_with_args(limit, true, args)
+    }
+
+    /// Which commit last changed each line of `path`, as of `rev` (or the
+    /// working tree).
+    pub fn blame(&self, path: &Path, rev: Option<&str>) -> Result<Vec<BlameLine>, Error> {
+        let mut command = self.git().command_parse(BlameLine::COMMAND);
+        if let Some(rev) = rev {
+            command.add_arg(rev);
+        }
+        let output = command
+            .with_arg("--")
+            .with_arg(path)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::BlameFailed {
+                path: path.to_owned(),
+                source,
+            })?;
+        BlameLine::parse_all(&String::from_utf8_lossy(output.stdout()))
+    }
+
+    /// The most recent commit that touched `path`, or `None` if it never
+    /// existed.
+    pub fn last_commit_for_path(&self, path: &Path) -> Result<Option<Commit>, Error> {
+        self.file_log(path, Some(1), false)
+            .map(|commits| commits.into_iter().next())
+    }
+
+    /// If `from_head` is set, this is logging `HEAD`, which might not exist
+    /// yet.
+    fn log_with_args<'a>(
+        &self,
+        limit: Option<usize>,
+        from_head: bool,
+        args: impl IntoIterator<Item = &'a std::ffi::OsStr>,
+    ) -> Result<Vec<Commit>, Error> {
+        const COMMAND: &str = "log -z";
+        let mut command = self.git().command_parse(COMMAND);
+        command.add_arg(Commit::FORMAT);
+        if let Some(limit) = limit {
+            command.add_arg("-n").add_arg(limit.to_string());
+        }
+        command.add_args(args);
+        match command.run_and_wait_for_string() {
+            Ok(output) => Commit::parse_all(COMMAND, &output),
+            Err(_) if from_head && !self.contains_commit("HEAD")? => Ok(Vec::new()),
+            Err(err) => Err(Error::LogFailed(err)),
+        }
+    }
+
+    pub fn latest_subject(&self) -> Result<String, Error> {
+        #[cfg(feature = "gix")]
+        if let Some(subject) =
+            crate::native::open(self.git()).and_then(|repo| crate::native::latest_subject(&repo))
+        {
+            return Ok(subject);
+        }
+        self.latest_commit("%s")
+    }
+
+    pub fn latest_body(&self) -> Result<String, Error> {
+        self.latest_commit("%b")
+    }
+
+    /// Whether the repo's path is the root of a git repo (or is a bare repo),
+    /// as opposed to missing, not a repo at all, or some directory inside
+    /// another repo.
+    pub fn is_valid(&self) -> bool {
+        self.check_valid().unwrap_or(false)
+    }
+
+    /// The same as [`Repo::is_valid`], but only `false` when git says this
+    /// isn't a repo (or is part of some other one), rather than whenever git
+    /// fails.
+    fn check_valid(&self) -> bossy::Result<bool> {
+        let root = self
+            .git()
+            .command_parse("rev-parse --is-bare-repository")
+            .run_and_wait_for_str(|s| s.trim() == "true")
+            .and_then(|bare| {
+                self.git()
+                    .command_parse(if bare {
+                        "rev-parse --absolute-git-dir"
+                    } else {
+                        "rev-parse --show-toplevel"
+                    })
+                    .run_and_wait_for_str(|s| PathBuf::from(s.trim()))
+            });
+        let root = match root {
+            Ok(root) => root,
+            Err(err) if crate::not_a_repo(&err) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        match (std::fs::canonicalize(root), self.path().canonicalize()) {
+            (Ok(root), Ok(path)) => Ok(root == path),
+            _ => Ok(false),
+        }
+    }
+
+    /// Whether `path` is the root of a git repo; see [`Repo::is_valid`].
+    pub fn is_repo(path: impl Into<PathBuf>) -> bool {
+        Self::from_path(path).is_valid()
+    }
+
+    /// The nearest directory that actually exists, for running commands that
+    /// don't need a repo before we've cloned one.
+    fn existing_ancestor(&self) -> &Path {
+        self.path()
+            .ancestors()
+            .find(|path| path.is_dir())
+            .unwrap_or_else(|| Path::new("."))
+    }
+
+    /// Checks that `url` points to a reachable repo that we have access to,
+    /// without prompting for credentials unless [`Repo::with_interactive`]
+    /// allows it.
+    pub fn verify_remote(&self, url: impl AsRef<std::ffi::OsStr>) -> Result<(), Error> {
+        let url = url.as_ref();
+        let mut command = self
+            .git()
+            .at(self.existing_ancestor())
+            .command_parse("ls-remote --exit-code")
+            .with_arg(url)
+            .with_arg("HEAD");
+        self.run_remote_in(&mut command, OutputMode::Capture, None)?
+            .map_err(|source| {
+                let url = url.to_owned();
+                // With `--exit-code`, 2 means that no refs matched.
+                if source.code() == Some(2) {
+                    return Error::RemoteHeadMissing { url };
+                }
+                RemoteFailure::error(url, source, |url, source| Error::RemoteVerifyFailed {
+                    url,
+                    source,
+                })
+            })?;
+        Ok(())
+    }
+
+    pub fn remote_url(&self, remote: &str) -> Result<String, Error> {
+        self.git()
+            .command_parse("remote get-url")
+            .with_arg(remote)
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+            .map_err(|source| Error::RemoteUrlFailed {
+                remote: remote.to_owned(),
+                source,
+            })
+    }
+
+    pub fn remotes(&self) -> Result<Vec<Remote>, Error> {
+        self.git()
+            .command_parse("remote -v")
+            .run_and_wait_for_str(Remote::parse_all)
+            .map_err(Error::RemoteListFailed)
+    }
+
+    pub fn add_remote(&self, name: &str, url: impl AsRef<std::ffi::OsStr>) -> Result<(), Error> {
+        self.git()
+            .command_parse("remote add --")
+            .with_arg(name)
+            .with_arg(url)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::RemoteAddFailed {
+                remote: name.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub fn remove_remote(&self, name: &str) -> Result<(), Error> {
+        self.git()
+            .command_parse("remote remove")
+            .with_arg(name)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::RemoteRemoveFailed {
+                remote: name.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub fn rename_remote(&self, old: &str, new: &str) -> Result<(), Error> {
+        self.git()
+            .command_parse("remote rename")
+            .with_args([old, new])
+            .run_and_wait_for_output()
+            .map_err(|source| Error::RemoteRenameFailed {
+                old: old.to_owned(),
+                new: new.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    pub fn set_remote_url(
+        &self,
+        name: &str,
+        url: impl AsRef<std::ffi::OsStr>,
+    ) -> Result<(), Error> {
+        let url = url.as_ref();
+        self.git()
+            .command_parse("remote set-url")
+            .with_arg(name)
+            .with_arg(url)
+            .run_and_wait_for_output()
+            .map_err(|source| Error::RemoteUrlUpdateFailed {
+                remote: name.to_owned(),
+                url: url.to_owned(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// The default branch of the remote, i.e. what `refs/remotes/<remote>/HEAD`
+    /// points to. If that ref isn't present locally, the remote is asked
+    /// directly.
+    pub fn default_branch(&self) -> Result<String, Error> {
+        let remote = self.remote();
+        let local = self
+            .git()
+            .command_parse("symbolic-ref --short -q")
+            .with_arg(format!("refs/remotes/{}/HEAD", remote))
+            .run_and_wait_for_str(|s| {
+                s.trim()
+                    .strip_prefix(remote)
+                    .and_then(|s| s.strip_prefix('/'))
+                    .filter(|branch| !branch.is_empty())
+                    .map(ToOwned::to_owned)
+            });
+        match local {
+            Ok(Some(branch)) => return Ok(branch),
+            Ok(None) => (),
+            Err(err) => log::info!("`{}/HEAD` isn't available locally: {}", remote, err),
+        }
+        self.git()
+            .command()
+            .with_args(["ls-remote", "--symref", remote, "HEAD"])
+            .run_and_wait_for_str(|s| {
+                s.lines()
+                    .filter_map(|line| line.strip_prefix("ref: refs/heads/"))
+                    .find_map(|line| line.strip_suffix("\tHEAD"))
+                    .map(ToOwned::to_owned)
+            })
+            .map_err(|source| Error::LsRemoteFailed {
+                remote: remote.to_owned(),
+                source,
+            })?
+            .ok_or_else(|| Error::DefaultBranchUnresolved {
+                remote: remote.to_owned(),
+            })
+    }
+
+    pub fn update(&self, url: impl AsRef<std::ffi::OsStr>) -> Result<UpdateOutcome, Error> {
+        self.update_branch(url, None)
+    }
+
+    pub fn update_branch(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+        branch: Option<&str>,
+    ) -> Result<UpdateOutcome, Error> {
+        self.update_with_options(
+            url,
+            &UpdateOptions {
+                branch: branch.map(ToOwned::to_owned),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Clones the repo if it isn't present (or recovers from an interrupted
+    /// clone, like [`Repo::update`]), points our remote at `url` if it moved,
+    /// and then checks out `rev`.
+    pub fn update_pinned(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+        rev: &str,
+    ) -> Result<UpdateOutcome, Error> {
+        let _lock = self.lock()?;
+        self.remove_if_invalid(false)?;
+        let old_head = if !self.path().is_dir() {
+            self.clone_from_url(url.as_ref(), &Default::default(), None)?;
+            None
+        } else {
+            self.sync_remote_url(url.as_ref())?;
+            Some(self.head_hash()?)
+        };
+        self.checkout(rev)?;
+        Ok(UpdateOutcome {
+            freshly_cloned: old_head.is_none(),
+            old_head,
+            new_head: self.head_hash()?,
+        })
+    }
+
+    pub fn update_with_excludes(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+        excludes: &[&str],
+    ) -> Result<UpdateOutcome, Error> {
+        self.update_with_options(
+            url,
+            &UpdateOptions {
+                clean_excludes: excludes.iter().map(|&pattern| pattern.to_owned()).collect(),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn create_parent_dir(&self) -> Result<&Path, Error> {
+        let parent = self
+            .path()
+            .parent()
+            .expect("developer error: `Repo` path was at root");
+        if !parent.is_dir() {
+            std::fs::create_dir_all(parent).map_err(|source| Error::ParentDirCreationFailed {
+                path: parent.to_owned(),
+                source,
+            })?;
+        }
+        Ok(parent)
+    }
+
+    pub fn init(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::init_with_options(path, &Default::default())
+    }
+
+    /// Creates a brand new repo at `path`, along with any missing parent
+    /// directories.
+    pub fn init_with_options(
+        path: impl Into<PathBuf>,
+        options: &InitOptions,
+    ) -> Result<Self, Error> {
+        let this = Self::from_path(path);
+        let parent = this.create_parent_dir()?;
+        let mut command = Git::new(parent).command();
+        command.add_arg("init");
+        if let Some(branch) = &options.initial_branch {
+            command.add_arg("--initial-branch").add_arg(branch);
+        }
+        if options.bare {
+            command.add_arg("--bare");
+        }
+        command
+            .with_arg(this.path())
+            .run_and_wait_for_output()
+            .map_err(Error::InitFailed)?;
+        Ok(this)
+    }
+
+    fn clone_from_url(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+        progress: Progress<'_>,
+    ) -> Result<(), Error> {
+        let mut command = self.clone_command(url, options, progress.is_some())?;
+        let result = self.run_remote(&mut command, progress);
+        self.finish_clone(result, options)
+    }
+
+    fn clone_command(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+        progress: bool,
+    ) -> Result<bossy::Command, Error> {
+        let parent = self.create_parent_dir()?;
+        let mut command = self.git().at(parent).command();
+        command.add_arg("clone");
+        if progress {
+            command.add_arg("--progress");
+        }
+        if self.kind() == RepoKind::Mirror {
+            // Mirrors get every ref, so depth and branch selection don't apply.
+            command.add_arg("--mirror");
+        } else {
+            if self.kind() == RepoKind::Bare {
+                command.add_arg("--bare");
+            }
+            options.clone.add_fetch_args(&mut command);
+            command.add_arg(if options.clone.single_branch {
+                "--single-branch"
+            } else {
+                "--no-single-branch"
+            });
+            if let Some(branch) = &options.branch {
+                command.add_arg("--branch").add_arg(branch);
+            }
+        }
+        command.add_arg("--origin").add_arg(self.remote());
+        if let Some(reference) = &options.clone.reference {
+            command.add_arg("--reference-if-able").add_arg(reference);
+            if options.clone.dissociate {
+                command.add_arg("--dissociate");
+            }
+        }
+        if options.clone.sparse_paths.is_some() && self.kind().has_working_tree() {
+            self.git()
+                .ensure_version(SPARSE_CHECKOUT_VERSION)
+                .map_err(Error::GitUnavailable)?;
+            command.add_arg("--sparse");
+        }
+        if options.clone.recurse_submodules && self.kind().has_working_tree() {
+            command.add_arg("--recurse-submodules");
+            if options.clone.depth.is_some() {
+                command.add_arg("--shallow-submodules");
+            }
+        }
+        command.add_arg(url).add_arg(self.path());
+        Ok(command)
+    }
+
+    /// Cleans up after a clone that was killed, or finishes setting up one
+    /// that succeeded.
+    fn finish_clone(
+        &self,
+        result: Result<bossy::Result<()>, Error>,
+        options: &UpdateOptions,
+    ) -> Result<(), Error> {
+        let path = self.path();
+        if let Err(Error::TimedOut { .. } | Error::Cancelled { .. }) = &result {
+            // git only cleans up after itself if it gets to exit normally.
+            if path.exists() {
+                if let Err(err) = std::fs::remove_dir_all(path) {
+                    log::error!("failed to remove interrupted clone at {:?}: {}", path, err);
+                }
+            }
+        }
+        result?.map_err(Error::CloneFailed)?;
+        if let Some(paths) = &options.clone.sparse_paths {
+            if self.kind().has_working_tree() {
+                self.set_sparse_paths(&paths.iter().map(String::as_str).collect::<Vec<_>>())?;
+            }
+        }
+        if self.kind() == RepoKind::Bare {
+            // Bare clones don't get a fetch refspec, so without this, fetches
+            // wouldn't update anything.
+            self.git()
+                .command()
+                .with_arg("config")
+                .with_arg(format!("remote.{}.fetch", self.remote()))
+                .with_arg("+refs/heads/*:refs/heads/*")
+                .run_and_wait_for_output()
+                .map_err(Error::CloneFailed)?;
+        }
+        Ok(())
+    }
+
+    fn fetch_bare(&self, url: &std::ffi::OsStr, progress: Progress<'_>) -> Result<(), Error> {
+        let mut command = self.fetch_bare_command(url, progress.is_some())?;
+        let result = self.run_remote(&mut command, progress);
+        self.fetched_from_remote(result)
+    }
+
+    fn fetch_bare_command(
+        &self,
+        url: &std::ffi::OsStr,
+        progress: bool,
+    ) -> Result<bossy::Command, Error> {
+        log::info!("updating bare repo at {:?}", self.path());
+        self.sync_remote_url(url)?;
+        // `remote update` is just `fetch` under the hood, but doesn't take
+        // `--progress`.
+        let mut command = if progress {
+            self.git().command_parse("fetch --prune --progress")
+        } else {
+            self.git().command_parse("remote update --prune")
+        };
+        command.add_arg(self.remote());
+        Ok(command)
+    }
+
+    /// Checks how a fetch from our remote went.
+    fn fetched_from_remote(&self, result: Result<bossy::Result<()>, Error>) -> Result<(), Error> {
+        result?.map_err(|source| Error::FetchFailed {
+            remote: self.remote().to_owned(),
+            source,
+        })
+    }
+
+    fn ensure_clean(&self, options: &UpdateOptions) -> Result<(), Error> {
+        if !options.force && self.kind().has_working_tree() {
+            let paths = self.dirty_paths()?;
+            if !paths.is_empty() {
+                return Err(Error::DirtyWorkingTree { paths });
+            }
+        }
+        Ok(())
+    }
+
+    /// A hidden path next to the repo for staging atomic updates.
+    fn sibling_path(&self, purpose: &str) -> PathBuf {
+        let name = self
+            .path()
+            .file_name()
+            .expect("developer error: `Repo` path had no file name");
+        self.path().with_file_name(format!(
+            ".{}.hit-{}-{}",
+            name.to_string_lossy(),
+            purpose,
+            std::process::id()
+        ))
+    }
+
+    /// Moves `staging` to our path, replacing whatever was there before.
+    fn swap_in(&self, staging: &Path) -> std::io::Result<()> {
+        if self.path().exists() {
+            let old = self.sibling_path("old");
+            std::fs::rename(self.path(), &old)?;
+            if let Err(err) = std::fs::rename(staging, self.path()) {
+                if let Err(err) = std::fs::rename(&old, self.path()) {
+                    log::error!(
+                        "failed to restore {:?} from {:?}: {}",
+                        self.path(),
+                        old,
+                        err
+                    );
+                }
+                return Err(err);
+            }
+            if let Err(err) = std::fs::remove_dir_all(&old) {
+                log::warn!("failed to remove old checkout at {:?}: {}", old, err);
+            }
+            Ok(())
+        } else {
+            std::fs::rename(staging, self.path())
+        }
+    }
+
+    fn update_atomically(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+        mut progress: Progress<'_>,
+    ) -> Result<UpdateOutcome, Error> {
+        let old_head = if self.path().is_dir() {
+            self.ensure_clean(options)?;
+            Some(self.head_hash()?)
+        } else {
+            None
+        };
+        let staging = Self {
+            path: self.sibling_path("staging"),
+            ..self.clone()
+        };
+        let remove_staging = || {
+            if let Err(err) = std::fs::remove_dir_all(staging.path()) {
+                log::warn!(
+                    "failed to remove staging directory {:?}: {}",
+                    staging.path(),
+                    err
+                );
+            }
+        };
+        if staging.path().exists() {
+            remove_staging();
+        }
+        if let Err(err) = staging.clone_from_url(url, options, crate::reborrow(&mut progress)) {
+            if staging.path().exists() {
+                remove_staging();
+            }
+            return Err(err);
+        }
+        if let Err(err) = self.swap_in(staging.path()) {
+            log::warn!(
+                "failed to swap {:?} into place, so updating in place instead: {}",
+                staging.path(),
+                err
+            );
+            remove_staging();
+            return self.update_in_place(url, options, progress);
+        }
+        Ok(UpdateOutcome {
+            freshly_cloned: old_head.is_none(),
+            old_head,
+            new_head: self.head_hash()?,
+        })
+    }
+
+    /// Runs `command`, which talks to a remote, killing it if it outlasts our
+    /// timeout or gets cancelled, and retrying it according to our retry
+    /// policy. The outer result only ever fails with [`Error::TimedOut`],
+    /// [`Error::Cancelled`], [`Error::GaveUp`], or
+    /// [`Error::AuthenticationRequired`].
+    fn run_remote(
+        &self,
+        command: &mut bossy::Command,
+        progress: Progress<'_>,
+    ) -> Result<bossy::Result<()>, Error> {
+        self.run_remote_in(command, self.output, progress)
+    }
+
+    /// The same as [`Repo::run_remote`], but with output going wherever
+    /// `mode` says rather than our output mode.
+    fn run_remote_in(
+        &self,
+        command: &mut bossy::Command,
+        mode: OutputMode,
+        progress: Progress<'_>,
+    ) -> Result<bossy::Result<()>, Error> {
+        command
+            .run_and_wait_within(mode, progress, &self.limits)
+            .map_err(|stopped| {
+                self.stopped(command.display(), stopped, RepoLock::is_held(self.path()))
+            })
+    }
+
+    /// The same as [`Repo::run_remote`], but without blocking the async
+    /// runtime. `locked` is whether we hold the repo lock, which
+    /// [`RepoLock::is_held`] can't tell us once it's left the thread that
+    /// took it.
+    #[cfg(feature = "tokio")]
+    async fn run_remote_async(
+        &self,
+        command: &mut bossy::Command,
+        locked: bool,
+    ) -> Result<bossy::Result<()>, Error> {
+        crate::run_and_wait_within_async(command, self.output, &self.limits)
+            .await
+            .map_err(|stopped| self.stopped(command.display(), stopped, locked))
+    }
+
+    /// Turns `stopped` into an error, dealing with what `command` left
+    /// behind if it was killed.
+    fn stopped(&self, command: &str, stopped: Stopped, locked: bool) -> Error {
+        let command = command.to_owned();
+        match stopped {
+            Stopped::TimedOut(elapsed) => {
+                self.after_kill(locked);
+                Error::TimedOut { command, elapsed }
+            }
+            Stopped::Cancelled => {
+                self.after_kill(locked);
+                Error::Cancelled { command }
+            }
+            Stopped::GaveUp { attempts, source } => Error::GaveUp {
+                command,
+                attempts,
+                source,
+            },
+            Stopped::AuthenticationRequired(source) => {
+                Error::AuthenticationRequired { command, source }
+            }
+        }
+    }
+
+    /// The lock files in the repo's git directory, which are stale if no git
+    /// is running there.
+    fn git_locks(&self) -> Vec<PathBuf> {
+        fn visit(dir: &Path, recursive: bool, locks: &mut Vec<PathBuf>) {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => return,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    if recursive {
+                        visit(&path, recursive, locks);
+                    }
+                } else if path.extension() == Some("lock".as_ref()) {
+                    locks.push(path);
+                }
+            }
+        }
+        let mut locks = Vec::new();
+        let git = self.git();
+        if let Ok(common_dir) = git.common_dir() {
+            visit(&common_dir, false, &mut locks);
+            visit(&common_dir.join("refs"), true, &mut locks);
+            // Linked worktrees keep their own index (and its lock) apart from
+            // everything else.
+            if let Ok(git_dir) = git.git_dir() {
+                if git_dir != common_dir {
+                    visit(&git_dir, false, &mut locks);
+                }
+            }
+        }
+        locks
+    }
+
+    /// Removes the lock files that a killed git can leave behind, since
+    /// they'd make every later command fail.
+    fn remove_stale_locks(&self) {
+        for path in self.git_locks() {
+            log::warn!("removing stale lock file {:?}", path);
+            if let Err(err) = std::fs::remove_file(&path) {
+                log::error!("failed to remove stale lock file {:?}: {}", path, err);
+            }
+        }
+    }
+
+    /// Deals with whatever locks the git we just killed left behind. They're
+    /// only removed with [`Repo::with_stale_lock_removal`] while we hold our
+    /// own lock, since otherwise they could belong to another git that's
+    /// still running.
+    fn after_kill(&self, locked: bool) {
+        if self.remove_stale_locks && locked {
+            self.remove_stale_locks();
+        } else {
+            for path in self.git_locks() {
+                log::warn!(
+                    "{:?} may have been left behind by the git that was killed; if git isn't running there, it can be removed",
+                    path
+                );
+            }
+        }
+    }
+
+    /// Points our remote at `url` if it isn't already.
+    fn sync_remote_url(&self, url: &std::ffi::OsStr) -> Result<(), Error> {
+        let remote = self.remote();
+        let current = self.remote_url(remote)?;
+        if url != std::ffi::OsStr::new(&current) {
+            log::info!(
+                "URL of remote {:?} changed from {:?} to {:?}",
+                remote,
+                current,
+                url
+            );
+            self.set_remote_url(remote, url)?;
+        }
+        Ok(())
+    }
+
+    fn fetch_and_reset(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+        progress: Progress<'_>,
+    ) -> Result<(), Error> {
+        log::info!(
+            "Updating `{}` repo...",
+            Path::new(
+                self.path()
+                    .file_name()
+                    .expect("developer error: `Repo` path had no file name")
+            )
+            .display()
+        );
+        let stashed = match options.strategy {
+            UpdateStrategy::HardReset => {
+                self.ensure_clean(options)?;
+                false
+            }
+            UpdateStrategy::StashAndReapply => self.stash_push(Some("hit-update"), true)?,
+            UpdateStrategy::Rebase => false,
+        };
+        let result = self.prepare_fetch(url, options).and_then(|branch| {
+            if options.strategy == UpdateStrategy::Rebase {
+                self.pull_rebase(self.remote(), &branch)?;
+                if options.clone.recurse_submodules {
+                    self.update_submodules(options)?;
+                }
+                return Ok(());
+            }
+            let mut command = self.fetch_branch_command(&branch, options, progress.is_some());
+            let result = self.run_remote(&mut command, progress);
+            self.fetched_from_remote(result)?;
+            self.reset_to_fetch_head(options)
+        });
+        if stashed {
+            // Local changes get reapplied even if the update failed.
+            let popped = self.stash_pop(0);
+            result?;
+            popped
+        } else {
+            result
+        }
+    }
+
+    /// Gets everything but the fetch itself ready for [`Repo::fetch_and_reset`],
+    /// returning the branch to fetch.
+    fn prepare_fetch(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+    ) -> Result<String, Error> {
+        self.sync_remote_url(url)?;
+        let branch = match &options.branch {
+            Some(branch) => branch.to_owned(),
+            None => self.default_branch()?,
+        };
+        if let Some(paths) = &options.clone.sparse_paths {
+            let current = self.sparse_paths()?;
+            if current.as_deref().map(normalize_sparse_paths) != Some(normalize_sparse_paths(paths))
+            {
+                self.set_sparse_paths(&paths.iter().map(String::as_str).collect::<Vec<_>>())?;
+            }
+        }
+        Ok(branch)
+    }
+
+    fn fetch_branch_command(
+        &self,
+        branch: &str,
+        options: &UpdateOptions,
+        progress: bool,
+    ) -> bossy::Command {
+        let mut command = self.git().command();
+        command.add_arg("fetch");
+        if progress {
+            command.add_arg("--progress");
+        }
+        options.clone.add_fetch_args(&mut command);
+        command.add_arg(self.remote()).add_arg(branch);
+        command
+    }
+
+    /// Stashes uncommitted changes, returning `true` if there was anything to
+    /// stash.
+    pub fn stash_push(
+        &self,
+        message: Option<&str>,
+        include_untracked: bool,
+    ) -> Result<bool, Error> {
+        let stash_head = || {
+            self.git()
+                .command_parse("rev-parse -q --verify refs/stash")
+                .run_and_wait_for_str(|s| s.trim().to_owned())
+                .ok()
+        };
+        let before = stash_head();
+        let mut command = self.git().command_parse("stash push");
+        if include_untracked {
+            command.add_arg("--include-untracked");
+        }
+        if let Some(message) = message {
+            command.add_arg("-m").add_arg(message);
+        }
+        command
+            .run_and_wait_in(self.output)
+            .map_err(Error::StashFailed)?;
+        Ok(stash_head() != before)
+    }
+
+    /// Stash entries, most recent first.
+    pub fn stash_list(&self) -> Result<Vec<StashEntry>, Error> {
+        const COMMAND: &str = "stash list -z --format=%H%x00%gs";
+        let output = self
+            .git()
+            .command_parse(COMMAND)
+            .run_and_wait_for_string()
+            .map_err(Error::StashListFailed)?;
+        let fields = output
+            .strip_suffix('\0')
+            .filter(|fields| !fields.is_empty())
+            .map(|fields| fields.split('\0').collect::<Vec<_>>())
+            .unwrap_or_default();
+        let entries = fields.chunks_exact(2);
+        if !entries.remainder().is_empty() {
+            return Err(Error::ParseFailed {
+                command: COMMAND,
+                output,
+            });
+        }
+        Ok(entries
+            .enumerate()
+            .map(|(index, fields)| StashEntry {
+                index,
+                message: fields[1].to_owned(),
+                hash: fields[0].to_owned(),
+            })
+            .collect())
+    }
+
+    /// Reapplies and drops stash entry `index`. If reapplying conflicts, the
+    /// entry is kept and this fails with [`Error::StashConflict`].
+    pub fn stash_pop(&self, index: usize) -> Result<(), Error> {
+        self.stash_reapply("pop", index)
+    }
+
+    /// Reapplies stash entry `index` without dropping it, failing with
+    /// [`Error::StashConflict`] on conflicts.
+    pub fn stash_apply(&self, index: usize) -> Result<(), Error> {
+        self.stash_reapply("apply", index)
+    }
+
+    pub fn stash_drop(&self, index: usize) -> Result<(), Error> {
+        self.git()
+            .command_parse("stash drop -q")
+            .with_arg(format!("stash@{{{}}}", index))
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::StashDropFailed { index, source })?;
+        Ok(())
+    }
+
+    fn stash_reapply(&self, subcommand: &str, index: usize) -> Result<(), Error> {
+        if let Err(err) = self
+            .git()
+            .command()
+            .with_args(["stash", subcommand])
+            .with_arg(format!("stash@{{{}}}", index))
+            .run_and_wait_in(self.output)
+        {
+            let paths = self.conflicted_paths()?;
+            return Err(if paths.is_empty() {
+                Error::StashReapplyFailed { index, source: err }
+            } else {
+                Error::StashConflict { paths }
+            });
+        }
+        Ok(())
+    }
+
+    /// Paths with unresolved merge conflicts.
+    fn conflicted_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let output = self
+            .git()
+            .command_parse("diff --name-only --diff-filter=U -z")
+            .run_and_wait_for_output()
+            .map_err(Error::ConflictCheckFailed)?;
+        Ok(output
+            .stdout()
+            .split(|b| *b == 0)
+            .filter(|path| !path.is_empty())
+            .map(crate::path_from_bytes)
+            .collect())
+    }
+
+    /// Whether there's an interrupted operation whose state lives in `name`
+    /// within the git dir, i.e. `rebase-merge`.
+    fn in_progress(&self, name: &str) -> Result<bool, Error> {
+        self.git()
+            .command_parse("rev-parse --git-path")
+            .with_arg(name)
+            .run_and_wait_for_str(|s| self.path().join(s.trim()).exists())
+            .map_err(Error::ConflictCheckFailed)
+    }
+
+    /// Merges `rev` into the current branch. If this conflicts, the merge is
+    /// aborted unless `leave_conflicts` is set; either way, this fails with
+    /// [`Error::MergeConflict`].
+    pub fn merge(&self, rev: &str, options: &MergeOptions) -> Result<(), Error> {
+        let mut command = self.git().command_parse("merge --no-edit");
+        if options.ff_only {
+            command.add_arg("--ff-only");
+        }
+        if options.no_ff {
+            command.add_arg("--no-ff");
+        }
+        if options.squash {
+            command.add_arg("--squash");
+        }
+        if let Err(source) = command.with_arg(rev).run_and_wait_in(self.output) {
+            let paths = self.conflicted_paths()?;
+            // Squash merges don't leave a `MERGE_HEAD` behind.
+            if paths.is_empty() && !self.in_progress("MERGE_HEAD")? {
+                return Err(Error::MergeFailed {
+                    rev: rev.to_owned(),
+                    source,
+                });
+            }
+            if !options.leave_conflicts {
+                self.git()
+                    .command_parse("reset --merge")
+                    .run_and_wait_in(self.output)
+                    .map_err(Error::AbortFailed)?;
+            }
+            return Err(Error::MergeConflict {
+                rev: rev.to_owned(),
+                paths,
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies the changes from each of `revs` in order. If this conflicts,
+    /// everything is aborted unless `leave_conflicts` is set; either way,
+    /// this fails with [`Error::CherryPickConflict`].
+    pub fn cherry_pick(&self, revs: &[&str], options: &PickOptions) -> Result<(), Error> {
+        self.pick("cherry-pick", revs, options)?
+            .map_err(|failure| match failure {
+                PickFailure::Failed(source) => Error::CherryPickFailed {
+                    revs: revs.iter().map(|rev| (*rev).to_owned()).collect(),
+                    source,
+                },
+                PickFailure::Conflict { commit, paths } => {
+                    Error::CherryPickConflict { commit, paths }
+                }
+            })
+    }
+
+    /// Applies the inverse of `rev`'s changes, handling conflicts like
+    /// [`Repo::cherry_pick`].
+    pub fn revert(&self, rev: &str, options: &PickOptions) -> Result<(), Error> {
+        self.pick("revert", &[rev], options)?
+            .map_err(|failure| match failure {
+                PickFailure::Failed(source) => Error::RevertFailed {
+                    rev: rev.to_owned(),
+                    source,
+                },
+                PickFailure::Conflict { commit, paths } => Error::RevertConflict { commit, paths },
+            })
+    }
+
+    fn pick(
+        &self,
+        subcommand: &'static str,
+        revs: &[&str],
+        options: &PickOptions,
+    ) -> Result<Result<(), PickFailure>, Error> {
+        let mut command = self.git().command();
+        command.add_arg(subcommand);
+        if options.no_commit {
+            command.add_arg("--no-commit");
+        }
+        if subcommand == "revert" {
+            command.add_arg("--no-edit");
+        }
+        let source = match command.with_args(revs).run_and_wait_for_output() {
+            Ok(_) => return Ok(Ok(())),
+            Err(source) => source,
+        };
+        let paths = self.conflicted_paths()?;
+        if paths.is_empty() {
+            return Ok(Err(PickFailure::Failed(source)));
+        }
+        // git reports the commit it was applying, i.e.
+        // "error: could not apply 1a2b3c4... subject".
+        let commit = source
+            .stderr()
+            .map(String::from_utf8_lossy)
+            .and_then(|stderr| {
+                stderr.lines().find_map(|line| {
+                    line.strip_prefix("error: could not apply ")
+                        .or_else(|| line.strip_prefix("error: could not revert "))
+                        .and_then(|rest| rest.split("...").next())
+                        .map(ToOwned::to_owned)
+                })
+            })
+            .or_else(|| revs.last().map(|rev| (*rev).to_owned()))
+            .unwrap_or_default();
+        if !options.leave_conflicts {
+            // The sequencer only exists for multi-commit operations, and
+            // aborting it also undoes any commits that were already applied.
+            let mut abort = if self.in_progress("sequencer")? {
+                self.git().command().with_args([subcommand, "--abort"])
+            } else {
+                self.git().command_parse("reset --merge")
+            };
+            abort
+                .run_and_wait_in(self.output)
+                .map_err(Error::AbortFailed)?;
+        }
+        Ok(Err(PickFailure::Conflict { commit, paths }))
+    }
+
+    /// The best common ancestor of `a` and `b`.
+    pub fn merge_base(&self, a: &str, b: &str) -> Result<String, Error> {
+        self.git()
+            .command_parse("merge-base")
+            .with_args([a, b])
+            .run_and_wait_for_str(|s| s.trim().to_owned())
+            .map_err(|source| {
+                let (a, b) = (a.to_owned(), b.to_owned());
+                // Exit code 1 means there's nothing in common.
+                if source.code() == Some(1) {
+                    Error::NoMergeBase { a, b }
+                } else {
+                    Error::MergeBaseFailed { a, b, source }
+                }
+            })
+    }
+
+    /// Fetches `branch` from `remote` and rebases local commits onto it,
+    /// stashing uncommitted changes in the meantime. If the rebase
+    /// conflicts, it's aborted, leaving the repo how it was.
+    pub fn pull_rebase(&self, remote: &str, branch: &str) -> Result<(), Error> {
+        if let Err(source) = self.run_remote(
+            &mut self
+                .git()
+                .command_parse("pull --rebase --autostash")
+                .with_arg(remote)
+                .with_arg(branch),
+            None,
+        )? {
+            if !self.in_progress("rebase-merge")? && !self.in_progress("rebase-apply")? {
+                return Err(Error::PullFailed {
+                    remote: remote.to_owned(),
+                    source,
+                });
+            }
+            let paths = self.conflicted_paths()?;
+            self.git()
+                .command_parse("rebase --abort")
+                .run_and_wait_in(self.output)
+                .map_err(Error::AbortFailed)?;
+            return Err(Error::RebaseConflict { paths });
+        }
+        Ok(())
+    }
+
+    /// Hard resets to `FETCH_HEAD`, discarding everything untracked.
+    fn reset_to_fetch_head(&self, options: &UpdateOptions) -> Result<(), Error> {
+        self.git()
+            .command_parse("reset --hard FETCH_HEAD")
+            .run_and_wait_in(self.output)
+            .map_err(Error::ResetFailed)?;
+        let mut command = self.git().command_parse("clean -dfx");
+        for pattern in &options.clean_excludes {
+            command.add_arg("--exclude").add_arg(pattern);
+        }
+        command
+            .run_and_wait_in(self.output)
+            .map_err(Error::CleanFailed)?;
+        if options.clone.recurse_submodules {
+            self.update_submodules(options)?;
+        }
+        Ok(())
+    }
+
+    fn update_submodules(&self, options: &UpdateOptions) -> Result<(), Error> {
+        log::info!("updating submodules of repo at {:?}", self.path());
+        let mut command = self
+            .git()
+            .command_parse("submodule update --init --recursive --checkout --force");
+        options.clone.add_depth_arg(&mut command);
+        command
+            .run_and_wait_in(self.output)
+            .map_err(Error::SubmoduleUpdateFailed)?;
+        Ok(())
+    }
+
+    /// Calls `f` with a [`Git`] rooted at each initialized submodule (and
+    /// their submodules too if `recursive` is set), returning what it
+    /// returned for each one by name. Nested submodules are named after their
+    /// parents, i.e. `parent/child`. A failure in one submodule doesn't stop
+    /// the rest from being visited.
+    ///
+    /// Only failing to list the top-level submodules fails the whole thing.
+    /// If a submodule's config can't be read, that's its result instead of
+    /// calling `f`, and if its own submodules can't be listed, that replaces
+    /// whatever `f` returned for it unless that was already an error.
+    pub fn submodule_foreach<T, E: From<Error>>(
+        &self,
+        recursive: bool,
+        mut f: impl FnMut(&str, Git<'_>) -> Result<T, E>,
+    ) -> Result<SubmoduleResults<T, E>, Error> {
+        let mut results = Vec::new();
+        Self::submodule_foreach_in(self.git(), None, recursive, &mut f, &mut results)?;
+        Ok(results)
+    }
+
+    /// Only fails if the submodules in `git` can't be listed.
+    fn submodule_foreach_in<T, E: From<Error>>(
+        git: Git<'_>,
+        parent: Option<&str>,
+        recursive: bool,
+        f: &mut impl FnMut(&str, Git<'_>) -> Result<T, E>,
+        results: &mut SubmoduleResults<T, E>,
+    ) -> Result<(), Error> {
+        for submodule in git.submodules().map_err(Error::SubmoduleListFailed)? {
+            let short_name = submodule.name().unwrap_or_default();
+            let name = match parent {
+                Some(parent) => format!("{}/{}", parent, short_name),
+                None => short_name.to_owned(),
+            };
+            let initialized = match git.config_get(
+                &format!("submodule.{}.url", short_name),
+                crate::config::Scope::Local,
+            ) {
+                Ok(url) => url.is_some(),
+                Err(err) => {
+                    results.push((name, Err(Error::SubmoduleListFailed(err).into())));
+                    continue;
+                }
+            };
+            let path = git.root().join(submodule.path());
+            if !initialized || !path.join(".git").exists() {
+                continue;
+            }
+            log::info!("visiting submodule {:?} at {:?}", name, path);
+            let submodule_git = git.at(&path);
+            let result = f(&name, submodule_git);
+            let index = results.len();
+            results.push((name.clone(), result));
+            if recursive {
+                if let Err(err) =
+                    Self::submodule_foreach_in(submodule_git, Some(&name), recursive, f, results)
+                {
+                    log::warn!("failed to visit submodules of {:?}: {}", name, err);
+                    let (_, result) = &mut results[index];
+                    if result.is_ok() {
+                        *result = Err(err.into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stages `paths`, including deletions.
+    pub fn add(&self, paths: &[&Path]) -> Result<(), Error> {
+        self.git()
+            .command_parse("add --")
+            .with_args(paths)
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::AddFailed {
+                paths: paths.iter().map(|path| path.to_path_buf()).collect(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// Stages every change in the working tree, including untracked files.
+    pub fn add_all(&self) -> Result<(), Error> {
+        self.git()
+            .command_parse("add --all")
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::AddFailed {
+                paths: vec![self.path().to_owned()],
+                source,
+            })?;
+        Ok(())
+    }
+
+    fn has_staged_changes(&self) -> Result<bool, Error> {
+        match self
+            .git()
+            .command_parse("diff --cached --quiet")
+            .run_and_wait_in(self.output)
+        {
+            Ok(_) => Ok(false),
+            // `--quiet` exits with 1 if there are differences.
+            Err(err) if err.code() == Some(1) => Ok(true),
+            Err(err) => Err(Error::StagedCheckFailed(err)),
+        }
+    }
+
+    /// Commits what's staged, returning the new commit's hash.
+    pub fn commit(&self, message: &str, options: &CommitOptions) -> Result<String, Error> {
+        if !options.allow_empty && !options.amend && !self.has_staged_changes()? {
+            return Err(Error::NothingToCommit);
+        }
+        let mut command = self.git().command_parse("commit -q");
+        if options.allow_empty {
+            command.add_arg("--allow-empty");
+        }
+        if options.amend {
+            command.add_arg("--amend");
+        }
+        if let Some(author) = &options.author {
+            command
+                .add_env_var("GIT_AUTHOR_NAME", &author.name)
+                .add_env_var("GIT_AUTHOR_EMAIL", &author.email);
+        }
+        if let Some(committer) = &options.committer {
+            command
+                .add_env_var("GIT_COMMITTER_NAME", &committer.name)
+                .add_env_var("GIT_COMMITTER_EMAIL", &committer.email);
+        }
+        command
+            .with_arg("-m")
+            .with_arg(message)
+            .run_and_wait_in(self.output)
+            .map_err(Error::CommitFailed)?;
+        self.head_hash()
+    }
+
+    /// Restricts the working tree to the directories in `paths` (and files at
+    /// the top level), using a cone-mode sparse checkout.
+    pub fn set_sparse_paths(&self, paths: &[&str]) -> Result<(), Error> {
+        self.git()
+            .ensure_version(SPARSE_CHECKOUT_VERSION)
+            .map_err(Error::GitUnavailable)?;
+        self.git()
+            .command_parse("sparse-checkout init --cone")
+            .run_and_wait_in(self.output)
+            .and_then(|()| {
+                self.git()
+                    .command_parse("sparse-checkout set --")
+                    .with_args(paths)
+                    .run_and_wait_in(self.output)
+            })
+            .map_err(|source| Error::SparseSetFailed {
+                paths: paths.iter().map(|path| (*path).to_owned()).collect(),
+                source,
+            })?;
+        Ok(())
+    }
+
+    /// The sparse checkout paths, or `None` if the checkout isn't sparse.
+    pub fn sparse_paths(&self) -> Result<Option<Vec<String>>, Error> {
+        let sparse = match self
+            .git()
+            .command_parse("config --type=bool core.sparseCheckout")
+            .run_and_wait_for_str(|s| s.trim() == "true")
+        {
+            Ok(sparse) => sparse,
+            // Exit code 1 means the option isn't set.
+            Err(err) if err.code() == Some(1) => false,
+            Err(err) => return Err(Error::SparseCheckFailed(err)),
+        };
+        if !sparse {
+            return Ok(None);
+        }
+        self.git()
+            .command_parse("sparse-checkout list")
+            .run_and_wait_for_str(|s| Some(s.lines().map(ToOwned::to_owned).collect()))
+            .map_err(Error::SparseCheckFailed)
+    }
+
+    /// Applies the patch at `patch` to the working tree. If it doesn't apply,
+    /// this fails with [`Error::PatchRejected`] listing the affected files.
+    pub fn apply(&self, patch: &Path, options: &ApplyOptions) -> Result<(), Error> {
+        let patch = std::path::absolute(patch).map_err(|source| Error::PatchPathFailed {
+            path: patch.to_owned(),
+            source,
+        })?;
+        let mut command = self.git().command_parse("apply");
+        if options.three_way {
+            command.add_arg("--3way");
+        }
+        if options.check {
+            command.add_arg("--check");
+        }
+        if options.index {
+            command.add_arg("--index");
+        }
+        match command.with_arg(&patch).run_and_wait_for_output() {
+            Ok(_) => Ok(()),
+            // Exit code 1 means the patch itself was fine, but some of it
+            // didn't apply. These are reported as `error: <path>: <reason>`.
+            Err(err) if err.code() == Some(1) => {
+                let stderr = err
+                    .stderr()
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                let mut paths = Vec::<PathBuf>::new();
+                for line in stderr.lines() {
+                    let path = line
+                        .strip_prefix("error: ")
+                        .filter(|line| !line.starts_with("patch failed: "))
+                        .and_then(|line| line.rfind(": ").map(|index| &line[..index]));
+                    if let Some(path) = path {
+                        if !paths.iter().any(|existing| existing == Path::new(path)) {
+                            paths.push(path.into());
+                        }
+                    }
+                }
+                if paths.is_empty() {
+                    Err(Error::ApplyFailed { patch, source: err })
+                } else {
+                    Err(Error::PatchRejected { patch, paths })
+                }
+            }
+            Err(source) => Err(Error::ApplyFailed { patch, source }),
+        }
+    }
+
+    /// Writes a patch file into `out_dir` for each commit in `range`,
+    /// returning their paths in order.
+    pub fn format_patch(&self, range: &str, out_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let out_dir = std::path::absolute(out_dir).map_err(|source| Error::PatchPathFailed {
+            path: out_dir.to_owned(),
+            source,
+        })?;
+        self.git()
+            .command_parse("format-patch -o")
+            .with_arg(out_dir)
+            .with_args([range, "--"])
+            .run_and_wait_for_str(|s| s.lines().map(PathBuf::from).collect())
+            .map_err(|source| Error::FormatPatchFailed {
+                range: range.to_owned(),
+                source,
+            })
+    }
+
+    /// Checks out `rev` with a detached `HEAD` in a new worktree at `path`,
+    /// which shares this repo's objects and refs.
+    pub fn add_worktree(&self, path: &Path, rev: &str) -> Result<Self, Error> {
+        let path = std::path::absolute(path).map_err(|source| Error::WorktreePathFailed {
+            path: path.to_owned(),
+            source,
+        })?;
+        self.git()
+            .command_parse("worktree add --detach")
+            .with_arg(&path)
+            .with_arg(rev)
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::WorktreeAddFailed {
+                path: path.clone(),
+                source,
+            })?;
+        Ok(Self::from_path(path)
+            .with_remote(self.remote())
+            .with_settings_of(self.git()))
+    }
+
+    /// Every worktree of the repo, starting with the main one.
+    pub fn list_worktrees(&self) -> Result<Vec<Worktree>, Error> {
+        self.git()
+            .command_parse("worktree list --porcelain")
+            .run_and_wait_for_str(Worktree::parse_all)
+            .map_err(Error::WorktreeListFailed)
+    }
+
+    /// Removes the worktree at `path`. Unless `force` is set, this fails if
+    /// the worktree has uncommitted changes.
+    pub fn remove_worktree(&self, path: &Path, force: bool) -> Result<(), Error> {
+        let path = std::path::absolute(path).map_err(|source| Error::WorktreePathFailed {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut command = self.git().command_parse("worktree remove");
+        if force {
+            command.add_arg("--force");
+        }
+        command
+            .with_arg(&path)
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::WorktreeRemoveFailed { path, source })?;
+        Ok(())
+    }
+
+    /// Checks the integrity of the object database.
+    pub fn fsck(&self) -> Result<FsckReport, Error> {
+        match self
+            .git()
+            .command_parse("fsck --no-progress")
+            .run_and_wait_for_output()
+        {
+            Ok(output) => Ok(FsckReport::parse(output.stdout(), output.stderr())),
+            // fsck exits with a bitmask of the problems it found, but 128
+            // means it couldn't run at all.
+            Err(err) if err.code() != Some(128) => {
+                let report = err
+                    .output()
+                    .map(|output| FsckReport::parse(output.stdout(), output.stderr()))
+                    .filter(|report| !report.is_healthy());
+                report.ok_or(Error::FsckFailed(err))
+            }
+            Err(err) => Err(Error::FsckFailed(err)),
+        }
+    }
+
+    /// `true` if [`Repo::fsck`] doesn't find any missing or corrupt objects.
+    /// This fails with [`Error::FsckFailed`] if fsck couldn't run at all,
+    /// which isn't a sign of corruption.
+    pub fn is_healthy(&self) -> Result<bool, Error> {
+        self.fsck().map(|report| report.is_healthy())
+    }
+
+    pub fn maintenance(&self, options: &MaintenanceOptions) -> Result<(), Error> {
+        if options.gc {
+            self.git()
+                .command_parse("gc --auto --quiet")
+                .run_and_wait_for_output()
+                .map_err(Error::GcFailed)?;
+        }
+        if options.prune {
+            self.git()
+                .command_parse("prune")
+                .run_and_wait_for_output()
+                .map_err(Error::PruneFailed)?;
+        }
+        if options.repack {
+            self.git()
+                .command_parse("repack -ad -q")
+                .run_and_wait_for_output()
+                .map_err(Error::RepackFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a bundle containing `range` (or all refs if `None`) to `dest`.
+    pub fn create_bundle(&self, dest: &Path, range: Option<&str>) -> Result<(), Error> {
+        let path = std::path::absolute(dest).map_err(|source| Error::BundlePathFailed {
+            path: dest.to_owned(),
+            source,
+        })?;
+        self.git()
+            .command_parse("bundle create")
+            .with_arg(&path)
+            .with_arg(range.unwrap_or("--all"))
+            .run_and_wait_for_output()
+            .map_err(|source| Error::BundleCreateFailed { path, source })?;
+        Ok(())
+    }
+
+    /// Runs `git bundle verify`, which also checks that the repo has the
+    /// commits the bundle requires.
+    pub fn verify_bundle(&self, src: &Path) -> Result<(), Error> {
+        let path = std::path::absolute(src).map_err(|source| Error::BundlePathFailed {
+            path: src.to_owned(),
+            source,
+        })?;
+        self.git()
+            .command_parse("bundle verify")
+            .with_arg(&path)
+            .run_and_wait_for_output()
+            .map_err(|err| Error::BundleInvalid {
+                reason: err
+                    .stderr()
+                    .map(|stderr| String::from_utf8_lossy(stderr).trim().to_owned())
+                    .filter(|stderr| !stderr.is_empty())
+                    .unwrap_or_else(|| err.to_string()),
+                path,
+            })?;
+        Ok(())
+    }
+
+    pub fn fetch_bundle(&self, src: &Path) -> Result<UpdateOutcome, Error> {
+        self.fetch_bundle_with_options(src, &Default::default())
+    }
+
+    /// Updates from a bundle the same way [`Repo::update_with_options`]
+    /// updates from a remote. If no branch is specified, the bundle's `HEAD`
+    /// is used, falling back to the current branch.
+    pub fn fetch_bundle_with_options(
+        &self,
+        src: &Path,
+        options: &UpdateOptions,
+    ) -> Result<UpdateOutcome, Error> {
+        let _lock = self.lock()?;
+        self.verify_bundle(src)?;
+        let path = std::path::absolute(src).map_err(|source| Error::BundlePathFailed {
+            path: src.to_owned(),
+            source,
+        })?;
+        let rev = match &options.branch {
+            Some(branch) => branch.to_owned(),
+            None => {
+                let heads = self
+                    .git()
+                    .command_parse("bundle list-heads")
+                    .with_arg(&path)
+                    .run_and_wait_for_string()
+                    .map_err(|source| Error::BundleListFailed {
+                        path: path.clone(),
+                        source,
+                    })?;
+                let has_head = heads
+                    .lines()
+                    .any(|line| line.split_whitespace().nth(1) == Some("HEAD"));
+                match self.current_branch()? {
+                    Some(branch) if !has_head => branch,
+                    _ => "HEAD".to_owned(),
+                }
+            }
+        };
+        let old_head = self.head_hash()?;
+        self.ensure_clean(options)?;
+        self.git()
+            .command()
+            .with_arg("fetch")
+            .with_arg(&path)
+            .with_arg(&rev)
+            .run_and_wait_in(self.output)
+            .map_err(|source| Error::BundleFetchFailed { path, source })?;
+        self.reset_to_fetch_head(options)?;
+        Ok(UpdateOutcome {
+            old_head: Some(old_head),
+            new_head: self.head_hash()?,
+            freshly_cloned: false,
+        })
+    }
+
+    /// Clones the repo if it isn't present, and otherwise fetches and hard
+    /// resets to the tracked branch.
+    pub fn update_with_options(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+        options: &UpdateOptions,
+    ) -> Result<UpdateOutcome, Error> {
+        self.update_inner(url.as_ref(), options, None)
+    }
+
+    /// The same as [`Repo::update`], but stops as soon as `token` is
+    /// cancelled, failing with [`Error::Cancelled`]. A cancelled clone is
+    /// removed, and a cancelled fetch leaves the repo as it was.
+    pub fn update_cancellable(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+        token: &CancellationToken,
+    ) -> Result<UpdateOutcome, Error> {
+        self.clone().with_cancellation(token).update(url)
+    }
+
+    /// The same as [`Repo::update`], but without blocking the async runtime.
+    /// The clone or fetch is waited for without tying up a thread; only the
+    /// quick local commands around it run on tokio's blocking pool. Dropping
+    /// the future kills the git command that's running, like cancelling
+    /// [`Repo::update_cancellable`] would.
+    ///
+    /// This needs to be polled from within a tokio runtime with its IO and
+    /// time drivers enabled.
+    #[cfg(feature = "tokio")]
+    pub async fn update_async(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+    ) -> Result<UpdateOutcome, Error> {
+        let token = CancellationToken::new();
+        let repo = self.clone().with_cancellation(&token);
+        let url = url.as_ref().to_owned();
+        crate::spawn_cancellable(token, async move {
+            let (lock, prepared, mut command) = {
+                let repo = repo.clone();
+                crate::blocking(move || repo.prepare_update(&url)).await?
+            };
+            let result = repo.run_remote_async(&mut command, lock.is_some()).await;
+            crate::blocking(move || {
+                let _lock = lock;
+                repo.finish_update(prepared, result)
+            })
+            .await
+        })
+        .await
+    }
+
+    /// The same as [`Repo::status`], but without blocking the async runtime;
+    /// see [`Repo::update_async`].
+    #[cfg(feature = "tokio")]
+    pub async fn status_async(&self) -> Result<Status, Error> {
+        let token = CancellationToken::new();
+        let repo = self.clone().with_cancellation(&token);
+        crate::spawn_cancellable(token, async move {
+            let (lock, prepared) = {
+                let repo = repo.clone();
+                crate::blocking(move || repo.prepare_status()).await?
+            };
+            let PreparedStatus { remote_head, fetch } = prepared;
+            if let Some(mut command) = fetch {
+                let result = repo.run_remote_async(&mut command, lock.is_some()).await;
+                repo.fetched_from_remote(result)?;
+            }
+            crate::blocking(move || {
+                let _lock = lock;
+                match remote_head {
+                    Some(remote_head) => repo.status_against(&remote_head),
+                    None => repo.status_local(),
+                }
+            })
+            .await
+        })
+        .await
+    }
+
+    /// Does everything [`Repo::update`] does before cloning or fetching,
+    /// returning that command instead of running it.
+    #[cfg(feature = "tokio")]
+    fn prepare_update(
+        &self,
+        url: &std::ffi::OsStr,
+    ) -> Result<(Option<RepoLock>, PreparedUpdate, bossy::Command), Error> {
+        let mut lock = self.lock()?;
+        self.remove_if_invalid(false)?;
+        let mut options = UpdateOptions::default();
+        // Carry the existing sparse configuration over, like `update_inner`.
+        if self.kind().has_working_tree() && self.path().is_dir() {
+            options.clone.sparse_paths = self.sparse_paths()?;
+        }
+        let (old_head, transfer, command) = if !self.path().is_dir() {
+            let command = self.clone_command(url, &options, false)?;
+            (None, Transfer::Clone, command)
+        } else if self.kind().has_working_tree() {
+            let old_head = self.head_hash()?;
+            self.ensure_clean(&options)?;
+            let branch = self.prepare_fetch(url, &options)?;
+            let command = self.fetch_branch_command(&branch, &options, false);
+            (Some(old_head), Transfer::Fetch, command)
+        } else {
+            let old_head = self.head_hash()?;
+            let command = self.fetch_bare_command(url, false)?;
+            (Some(old_head), Transfer::FetchBare, command)
+        };
+        if let Some(lock) = &mut lock {
+            lock.detach();
+        }
+        let prepared = PreparedUpdate {
+            options,
+            old_head,
+            transfer,
+        };
+        Ok((lock, prepared, command))
+    }
+
+    /// Does everything [`Repo::update`] does after cloning or fetching.
+    #[cfg(feature = "tokio")]
+    fn finish_update(
+        &self,
+        prepared: PreparedUpdate,
+        result: Result<bossy::Result<()>, Error>,
+    ) -> Result<UpdateOutcome, Error> {
+        let PreparedUpdate {
+            options,
+            old_head,
+            transfer,
+        } = prepared;
+        match transfer {
+            Transfer::Clone => self.finish_clone(result, &options)?,
+            Transfer::Fetch => {
+                self.fetched_from_remote(result)?;
+                self.reset_to_fetch_head(&options)?;
+            }
+            Transfer::FetchBare => self.fetched_from_remote(result)?,
+        }
+        Ok(UpdateOutcome {
+            freshly_cloned: old_head.is_none(),
+            old_head,
+            new_head: self.head_hash()?,
+        })
+    }
+
+    /// Does everything [`Repo::status`] does before fetching.
+    #[cfg(feature = "tokio")]
+    fn prepare_status(&self) -> Result<(Option<RepoLock>, PreparedStatus), Error> {
+        let mut lock = if self.path().exists() {
+            self.lock()?
+        } else {
+            None
+        };
+        let prepared = if !self.path().is_dir() {
+            PreparedStatus {
+                remote_head: None,
+                fetch: None,
+            }
+        } else if !self.kind().has_working_tree() {
+            let remote_head = self.remote_head()?;
+            let fetch = if self.contains_commit(&remote_head)? {
+                None
+            } else {
+                Some(self.fetch_commit_command(&remote_head))
+            };
+            PreparedStatus {
+                remote_head: Some(remote_head),
+                fetch,
+            }
+        } else {
+            PreparedStatus {
+                remote_head: None,
+                fetch: Some(self.fetch_command(&Default::default())),
+            }
+        };
+        if let Some(lock) = &mut lock {
+            lock.detach();
+        }
+        Ok((lock, prepared))
+    }
+
+    fn with_cancellation(self, token: &CancellationToken) -> Self {
+        Self {
+            limits: Limits {
+                cancel: Some(token.clone()),
+                ..self.limits
+            },
+            ..self
+        }
+    }
+
+    /// The same as [`Repo::update`], but calls `progress` as the clone or
+    /// fetch makes progress. Since git's stderr is consumed along the way, its
+    /// other messages are logged instead of being included in errors.
+    pub fn update_with_progress(
+        &self,
+        url: impl AsRef<std::ffi::OsStr>,
+        mut progress: impl FnMut(ProgressEvent),
+    ) -> Result<UpdateOutcome, Error> {
+        self.update_inner(url.as_ref(), &Default::default(), Some(&mut progress))
+    }
+
+    fn update_inner(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+        mut progress: Progress<'_>,
+    ) -> Result<UpdateOutcome, Error> {
+        let _lock = self.lock()?;
+        self.remove_if_invalid(options.force)?;
+        // Carry the existing sparse configuration over to any fresh clones.
+        let inherited;
+        let options = match options.clone.sparse_paths {
+            None if self.kind().has_working_tree() && self.path().is_dir() => {
+                match self.sparse_paths()? {
+                    Some(paths) => {
+                        inherited = UpdateOptions {
+                            clone: CloneOptions {
+                                sparse_paths: Some(paths),
+                                ..options.clone.clone()
+                            },
+                            ..options.clone()
+                        };
+                        &inherited
+                    }
+                    None => options,
+                }
+            }
+            _ => options,
+        };
+        let mut outcome = if options.verify && self.path().is_dir() && !self.is_healthy()? {
+            self.remove_corrupt(options)?;
+            self.update_in_place(url, options, crate::reborrow(&mut progress))?
+        } else if options.atomic {
+            self.update_atomically(url, options, crate::reborrow(&mut progress))?
+        } else {
+            self.update_in_place(url, options, crate::reborrow(&mut progress))?
+        };
+        if options.verify && !self.is_healthy()? {
+            self.remove_corrupt(options)?;
+            outcome = self.update_in_place(url, options, progress)?;
+            let report = self.fsck()?;
+            if !report.is_healthy() {
+                return Err(Error::Corrupt {
+                    path: self.path().to_owned(),
+                    report,
+                });
+            }
+        }
+        if options.auto_gc {
+            let gc_only = MaintenanceOptions {
+                gc: true,
+                prune: false,
+                repack: false,
+            };
+            if let Err(err) = self.maintenance(&gc_only) {
+                log::warn!("maintenance failed for repo at {:?}: {}", self.path(), err);
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Removes whatever's at our path if it isn't a valid repo, so that it
+    /// can be cloned again. Only an interrupted clone is removed without
+    /// `force`; anything else fails with [`Error::NotARepo`].
+    fn remove_if_invalid(&self, force: bool) -> Result<(), Error> {
+        let valid = !self.path().is_dir()
+            || self
+                .check_valid()
+                .map_err(|source| Error::ValidityCheckFailed {
+                    path: self.path().to_owned(),
+                    source,
+                })?;
+        if !valid {
+            // An interrupted clone leaves behind a `.git` that git doesn't
+            // recognize, which we can safely blow away, but anything else
+            // could be somebody's data.
+            let interrupted_clone = std::fs::read_dir(self.path())
+                .map(|entries| {
+                    entries
+                        .filter_map(Result::ok)
+                        .all(|entry| entry.file_name() == ".git")
+                })
+                .unwrap_or(false);
+            if !interrupted_clone && !force {
+                return Err(Error::NotARepo {
+                    path: self.path().to_owned(),
+                });
+            }
+            log::warn!(
+                "{:?} isn't a valid repo, probably due to an interrupted clone; removing it and cloning again",
+                self.path()
+            );
+            std::fs::remove_dir_all(self.path()).map_err(|source| {
+                Error::InvalidRepoRemovalFailed {
+                    path: self.path().to_owned(),
+                    source,
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Removes a corrupt repo so that it can be cloned again, which is subject
+    /// to the same rules as discarding uncommitted changes.
+    fn remove_corrupt(&self, options: &UpdateOptions) -> Result<(), Error> {
+        self.ensure_clean(options)?;
+        log::warn!(
+            "repo at {:?} is corrupt; removing it and cloning again",
+            self.path()
+        );
+        std::fs::remove_dir_all(self.path()).map_err(|source| Error::InvalidRepoRemovalFailed {
+            path: self.path().to_owned(),
+            source,
+        })
+    }
+
+    fn update_in_place(
+        &self,
+        url: &std::ffi::OsStr,
+        options: &UpdateOptions,
+        progress: Progress<'_>,
+    ) -> Result<UpdateOutcome, Error> {
+        let old_head = if !self.path().is_dir() {
+            self.clone_from_url(url, options, progress)?;
+            None
+        } else {
+            let old_head = self.head_hash()?;
+            if self.kind().has_working_tree() {
+                self.fetch_and_reset(url, options, progress)?;
+            } else {
+                self.fetch_bare(url, progress)?;
+            }
+            Some(old_head)
+        };
+        Ok(UpdateOutcome {
+            freshly_cloned: old_head.is_none(),
+            old_head,
+            new_head: self.head_hash()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Scope,
+        fixtures::{FixtureRepo, TempDir, DEFAULT_BRANCH},
+    };
+
+    /// A `file://` URL for `path`, for when git needs to treat a local repo
+    /// like a remote one.
+    fn file_url(path: &Path) -> String {
+        let path = path.to_str().unwrap().replace('\\', "/");
+        // Windows paths start with a drive letter rather than a slash.
+        format!("file:///{}", path.trim_start_matches('/'))
+    }
+
+    /// An upstream with a single commit.
+    fn upstream(dir: &TempDir) -> FixtureRepo {
+        let upstream = FixtureRepo::new(dir.path().join("upstream")).unwrap();
+        upstream.commit_file("file", "1", "first").unwrap();
+        upstream
+    }
+
+    #[test]
+    fn update_follows_remote_default_branch() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let repo = Repo::from_path(dir.path().join("clone"));
+        repo.update(upstream.url()).unwrap();
+        let head = upstream.commit_file("file", "2", "second").unwrap();
+        assert_eq!(repo.update(upstream.url()).unwrap().new_head, head);
+        assert_eq!(repo.default_branch().unwrap(), DEFAULT_BRANCH);
+        assert_eq!(
+            repo.current_branch().unwrap().as_deref(),
+            Some(DEFAULT_BRANCH)
+        );
+        assert!(repo
+            .git()
+            .command_parse("rev-parse --verify -q refs/remotes/origin/master")
+            .run_and_wait_for_output()
+            .is_err());
+    }
+
+    #[test]
+    fn update_branch_overrides_default_branch() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        upstream.branch("release").unwrap();
+        let release = upstream.commit_file("file", "2", "release").unwrap();
+        upstream.checkout(DEFAULT_BRANCH).unwrap();
+        let repo = Repo::from_path(dir.path().join("clone"));
+        let outcome = repo.update_branch(upstream.url(), Some("release")).unwrap();
+        assert_eq!(outcome.new_head, release);
+        assert_eq!(repo.current_branch().unwrap().as_deref(), Some("release"));
+    }
+
+    #[test]
+    fn default_branch_unresolved_when_remote_head_is_detached() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let repo = Repo::from_path(dir.path().join("clone"));
+        repo.update(upstream.url()).unwrap();
+        upstream
+            .git()
+            .command_parse("checkout -q --detach")
+            .run_and_wait()
+            .unwrap();
+        repo.git()
+            .command_parse("remote set-head origin -d")
+            .run_and_wait()
+            .unwrap();
+        assert!(matches!(
+            repo.default_branch(),
+            Err(Error::DefaultBranchUnresolved { remote }) if remote == "origin"
+        ));
+    }
+
+    /// A clone of an upstream that ignores everything these tests leave
+    /// lying around, so they don't count as uncommitted changes.
+    fn clone_with_ignores(dir: &TempDir) -> (FixtureRepo, Repo) {
+        let upstream = upstream(dir);
+        upstream
+            .commit_file(
+                ".gitignore",
+                "target/\nnode_modules/\n.cache/\nbuild/\n",
+                "ignore",
+            )
+            .unwrap();
+        let repo = Repo::from_path(dir.path().join("clone"));
+        repo.update(upstream.url()).unwrap();
+        for path in ["target/a", "node_modules/b", "sub/.cache/c", "build/d"] {
+            let path = repo.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "junk").unwrap();
+        }
+        (upstream, repo)
+    }
+
+    #[test]
+    fn update_keeps_excluded_files() {
+        let dir = TempDir::new().unwrap();
+        let (upstream, repo) = clone_with_ignores(&dir);
+        repo.update_with_excludes(upstream.url(), &["/node_modules", ".cache"])
+            .unwrap();
+        assert!(repo.path().join("node_modules/b").exists());
+        assert!(repo.path().join("sub/.cache/c").exists());
+        assert!(!repo.path().join("target/a").exists());
+        assert!(!repo.path().join("build/d").exists());
+    }
+
+    #[test]
+    fn update_keeps_target_by_default() {
+        let dir = TempDir::new().unwrap();
+        let (upstream, repo) = clone_with_ignores(&dir);
+        repo.update(upstream.url()).unwrap();
+        assert!(repo.path().join("target/a").exists());
+        assert!(!repo.path().join("node_modules/b").exists());
+    }
+
+    #[test]
+    fn update_with_no_excludes_cleans_everything() {
+        let dir = TempDir::new().unwrap();
+        let (upstream, repo) = clone_with_ignores(&dir);
+        repo.update_with_excludes(upstream.url(), &[]).unwrap();
+        for path in ["target", "node_modules", "sub", "build"] {
+            assert!(!repo.path().join(path).exists(), "{} survived", path);
+        }
+    }
+
+    #[test]
+    fn latest_commit_keeps_format_whole() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let repo = Repo::from_path(upstream.path());
+        assert_eq!(
+            repo.latest_commit("%an %ae").unwrap(),
+            "hit hit@example.com"
+        );
+        assert_eq!(
+            repo.latest_commit("'%s' \"%an\"").unwrap(),
+            "'first' \"hit\""
+        );
+    }
+
+    #[test]
+    fn current_branch_on_branch_detached_and_empty() {
+        let dir = TempDir::new().unwrap();
+        let empty = FixtureRepo::new(dir.path().join("empty")).unwrap();
+        let repo = Repo::from_path(empty.path());
+        // An unborn branch is still the checked out branch.
+        assert_eq!(
+            repo.current_branch().unwrap().as_deref(),
+            Some(DEFAULT_BRANCH)
+        );
+        assert!(!repo.is_detached().unwrap());
+
+        let upstream = upstream(&dir);
+        upstream.branch("feature").unwrap();
+        let repo = Repo::from_path(upstream.path());
+        assert_eq!(repo.current_branch().unwrap().as_deref(), Some("feature"));
+        assert!(!repo.is_detached().unwrap());
+
+        repo.checkout("HEAD").unwrap();
+        assert_eq!(repo.current_branch().unwrap(), None);
+        assert!(repo.is_detached().unwrap());
+    }
+
+    #[test]
+    fn update_moves_origin_to_new_url() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let old = upstream.push_to_bare(dir.path().join("old.git")).unwrap();
+        let repo = Repo::from_path(dir.path().join("clone"));
+        repo.update(&old).unwrap();
+        assert_eq!(repo.remote_url("origin").unwrap(), old.to_str().unwrap());
+
+        let head = upstream.commit_file("file", "2", "after moving").unwrap();
+        let new = upstream.push_to_bare(dir.path().join("new.git")).unwrap();
+        assert_eq!(repo.update(&new).unwrap().new_head, head);
+        assert_eq!(repo.remote_url("origin").unwrap(), new.to_str().unwrap());
+    }
+
+    #[test]
+    fn update_recovers_from_interrupted_clone() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let repo = Repo::from_path(dir.path().join("clone"));
+        // All that's left is a `.git` git doesn't recognize.
+        std::fs::create_dir_all(repo.path().join(".git/objects")).unwrap();
+        assert!(!repo.is_valid());
+        assert!(repo.update(upstream.url()).unwrap().freshly_cloned);
+        assert!(repo.is_valid());
+
+        let empty = Repo::from_path(dir.path().join("empty"));
+        std::fs::create_dir_all(empty.path()).unwrap();
+        assert!(empty.update(upstream.url()).unwrap().freshly_cloned);
+    }
+
+    #[test]
+    fn update_only_replaces_broken_checkout_with_force() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let repo = Repo::from_path(dir.path().join("clone"));
+        repo.update(upstream.url()).unwrap();
+        std::fs::remove_dir_all(repo.path().join(".git")).unwrap();
+        std::fs::create_dir(repo.path().join(".git")).unwrap();
+        std::fs::write(repo.path().join("precious"), "data").unwrap();
+        assert!(matches!(
+            repo.update(upstream.url()),
+            Err(Error::NotARepo { .. })
+        ));
+        assert!(repo.path().join("precious").exists());
+
+        let forced = UpdateOptions {
+            force: true,
+            ..Default::default()
+        };
+        let outcome = repo.update_with_options(upstream.url(), &forced).unwrap();
+        assert!(outcome.freshly_cloned);
+        assert!(repo.is_valid());
+        assert!(!repo.path().join("precious").exists());
+    }
+
+    #[test]
+    fn update_keeps_checkout_when_git_fails() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let path = dir.path().join("clone");
+        Repo::from_path(&path).update(upstream.url()).unwrap();
+        let repo = Repo::from_path(&path).with_program(dir.path().join("no-such-git"));
+        assert!(!repo.is_valid());
+        assert!(matches!(
+            repo.update(upstream.url()),
+            Err(Error::ValidityCheckFailed { .. })
+        ));
+        assert!(Repo::is_repo(&path));
+    }
+
+    #[test]
+    fn filtered_clone_is_partial_and_has_status() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        upstream
+            .git()
+            .config_set(
+                "uploadpack.allowFilter",
+                "true",
+                crate::config::Scope::Local,
+            )
+            .unwrap();
+        let url = file_url(upstream.path());
+        let options = UpdateOptions {
+            clone: CloneOptions {
+                filter: Some("blob:none".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let repo = Repo::from_path(dir.path().join("clone"));
+        repo.update_with_options(&url, &options).unwrap();
+        assert_eq!(
+            repo.git()
+                .config_get(
+                    "remote.origin.partialclonefilter",
+                    crate::config::Scope::Local
+                )
+                .unwrap()
+                .as_deref(),
+            Some("blob:none")
+        );
+        assert_eq!(repo.status().unwrap(), Status::Fresh);
+
+        let head = upstream.commit_file("file", "2", "second").unwrap();
+        assert_eq!(repo.status().unwrap(), Status::Behind(1));
+        assert_eq!(
+            repo.update_with_options(&url, &options).unwrap().new_head,
+            head
+        );
+        assert_eq!(repo.status().unwrap(), Status::Fresh);
+        assert_eq!(
+            std::fs::read_to_string(repo.path().join("file")).unwrap(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn reference_clone_works_normally() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let url = file_url(upstream.path());
+        // git can't borrow objects from a shallow clone.
+        let cache = Repo::from_path(dir.path().join("cache"));
+        let full = UpdateOptions {
+            clone: CloneOptions::full(),
+            ..Default::default()
+        };
+        cache.update_with_options(&url, &full).unwrap();
+        let alternates = |repo: &Repo| repo.path().join(".git/objects/info/alternates");
+        for (name, reference, dissociate) in [
+            ("shared", cache.path().to_owned(), false),
+            ("dissociated", cache.path().to_owned(), true),
+            ("missing", dir.path().join("no-such-cache"), false),
+        ] {
+            let options = UpdateOptions {
+                clone: CloneOptions {
+                    reference: Some(reference.clone()),
+                    dissociate,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let repo = Repo::from_path(dir.path().join(name));
+            repo.update_with_options(&url, &options).unwrap();
+            assert_eq!(repo.status().unwrap(), Status::Fresh, "{}", name);
+            assert_eq!(repo.latest_subject().unwrap(), "first", "{}", name);
+            assert_eq!(
+                alternates(&repo).exists(),
+                reference.exists() && !dissociate,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn diff_stat_parses_numstat() {
+        let stat = DiffStat::parse("3\t1\tsrc/lib.rs\n-\t-\tlogo.png\n0\t5\tREADME.md\n").unwrap();
+        assert_eq!(stat.files_changed, 3);
+        assert_eq!(stat.insertions, 3);
+        assert_eq!(stat.deletions, 6);
+        assert_eq!(stat.binary_files, 1);
+        assert!(matches!(
+            DiffStat::parse("lots\tof\tchanges\n"),
+            Err(Error::ParseFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn diff_stat_counts_binary_files() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let from = upstream.commit_file("text", "a\nb\nc\n", "text").unwrap();
+        upstream
+            .commit_file("text", "a\nB\nc\nd\n", "edit")
+            .unwrap();
+        let to = upstream
+            .commit_file("image.bin", [0u8, 159, 146, 150, 0, 1], "binary")
+            .unwrap();
+        let stat = Repo::from_path(upstream.path())
+            .diff_stat(&from, &to)
+            .unwrap();
+        assert_eq!(stat.files_changed, 2);
+        assert_eq!(stat.insertions, 2);
+        assert_eq!(stat.deletions, 1);
+        assert_eq!(stat.binary_files, 1);
+    }
+
+    /// Environment pointing git at `config` instead of the user's own global
+    /// config.
+    fn global_config(config: &Path) -> Vec<(OsString, OsString)> {
+        vec![("GIT_CONFIG_GLOBAL".into(), config.into())]
+    }
+
+    #[test]
+    fn with_env_applies_to_every_command() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let config = dir.path().join("gitconfig");
+        let ignore = dir.path().join("ignore");
+        std::fs::write(&ignore, "*.hit-ignored\n").unwrap();
+        let env = global_config(&config);
+        let repo = Repo::from_path(dir.path().join("clone")).with_env(&env);
+        repo.update(upstream.url()).unwrap();
+        repo.git()
+            .config_set("core.excludesFile", &ignore, Scope::Global)
+            .unwrap();
+        assert!(std::fs::read_to_string(&config)
+            .unwrap()
+            .contains("excludesFile"));
+        assert_eq!(
+            repo.git()
+                .config_get("core.excludesFile", Scope::Global)
+                .unwrap()
+                .map(PathBuf::from),
+            Some(ignore)
+        );
+
+        std::fs::write(repo.path().join("build.hit-ignored"), "").unwrap();
+        assert!(!repo.is_dirty().unwrap());
+        assert_eq!(repo.status().unwrap(), Status::Fresh);
+        // Without it, the file isn't ignored.
+        assert!(Repo::from_path(repo.path()).is_dirty().unwrap());
+    }
+
+    #[test]
+    fn output_parsing_ignores_user_config() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let config = dir.path().join("gitconfig");
+        std::fs::write(
+            &config,
+            "[log]\n\tshowSignature = true\n\tdecorate = full\n\
+             [format]\n\tpretty = fuller\n\
+             [color]\n\tui = always\n\
+             [status]\n\tshort = true\n\tbranch = true\n\
+             [column]\n\tui = always\n",
+        )
+        .unwrap();
+        let env = global_config(&config);
+        let repo = Repo::from_path(dir.path().join("clone")).with_env(&env);
+        let head = repo.update(upstream.url()).unwrap().new_head;
+        // `log.showSignature` only prints anything for signed commits, and
+        // this signature is bogus enough to get a screenful of complaints.
+        let tree = repo
+            .git()
+            .command_parse("rev-parse HEAD^{tree}")
+            .run_and_wait_for_str(|tree| tree.trim_end().to_owned())
+            .unwrap();
+        let object = dir.path().join("signed");
+        std::fs::write(
+            &object,
+            format!(
+                "tree {}\nparent {}\nauthor hit <hit@example.com> 1 +0000\n\
+                 committer hit <hit@example.com> 1 +0000\n\
+                 gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEzBAABCAAdFiEE\n \
+                 -----END PGP SIGNATURE-----\n\nsigned\n",
+                tree, head
+            ),
+        )
+        .unwrap();
+        let signed = repo
+            .git()
+            .command_parse("hash-object -t commit -w")
+            .with_arg(&object)
+            .run_and_wait_for_str(|hash| hash.trim_end().to_owned())
+            .unwrap();
+        repo.git()
+            .command_parse("reset -q --hard")
+            .with_arg(&signed)
+            .run_and_wait()
+            .unwrap();
+        assert_eq!(repo.latest_subject().unwrap(), "signed");
+        assert_eq!(repo.head_hash().unwrap(), signed);
+        assert!(!repo.is_dirty().unwrap());
+        assert_eq!(repo.status().unwrap(), Status::Ahead(1));
+        std::fs::write(repo.path().join("new"), "").unwrap();
+        assert_eq!(repo.dirty_paths().unwrap(), [PathBuf::from("new")]);
+    }
+
+    #[cfg(feature = "serde")]
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn repo_serializes_only_what_identifies_it() {
+        let auth = Auth::new(
+            "https://example.com",
+            crate::Credentials::BearerToken("secret".to_owned()),
+        );
+        let env = [("GIT_SSH_COMMAND".into(), "ssh -i key".into())];
+        let repo = Repo::from_path("deps/sdk")
+            .with_remote("upstream")
+            .with_kind(RepoKind::Mirror)
+            .with_env(&env)
+            .with_auth(Some(auth))
+            .with_interactive(true);
+        let json = serde_json::to_string(&repo).unwrap();
+        assert!(!json.contains("secret"), "{}", json);
+        assert!(!json.contains("GIT_SSH_COMMAND"), "{}", json);
+        let back = round_trip(&repo);
+        assert_eq!(back, repo);
+        assert!(back.env().is_empty());
+        assert!(!back.interactive());
+
+        let minimal: Repo = serde_json::from_str(r#"{"path": "deps/sdk"}"#).unwrap();
+        assert_eq!(minimal, Repo::from_path("deps/sdk"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn results_round_trip() {
+        for status in [
+            Status::Missing,
+            Status::Fresh,
+            Status::Ahead(1),
+            Status::Behind(2),
+            Status::Diverged {
+                ahead: 3,
+                behind: 4,
+            },
+        ] {
+            assert_eq!(round_trip(&status), status);
+        }
+        let outcome = UpdateOutcome {
+            old_head: Some("a".repeat(40)),
+            new_head: "b".repeat(40),
+            freshly_cloned: false,
+        };
+        assert_eq!(round_trip(&outcome), outcome);
+        let commit = Commit {
+            hash: "a".repeat(40),
+            short_hash: "a".repeat(7),
+            author_name: "hit".to_owned(),
+            author_email: "hit@example.com".to_owned(),
+            timestamp: 1_600_000_000,
+            subject: "first".to_owned(),
+            body: "more\n".to_owned(),
+        };
+        assert_eq!(round_trip(&commit), commit);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn options_round_trip_and_default_missing_fields() {
+        let options = UpdateOptions {
+            branch: Some("release".to_owned()),
+            clone: CloneOptions {
+                reference: Some("../cache".into()),
+                sparse_paths: Some(vec!["src".to_owned()]),
+                ..CloneOptions::full()
+            },
+            force: true,
+            strategy: UpdateStrategy::Rebase,
+            ..Default::default()
+        };
+        assert_eq!(round_trip(&options), options);
+        let options: UpdateOptions = serde_json::from_str(r#"{"force": true}"#).unwrap();
+        assert_eq!(
+            options,
+            UpdateOptions {
+                force: true,
+                ..Default::default()
+            }
+        );
+        let init: InitOptions = serde_json::from_str(r#"{"bare": true}"#).unwrap();
+        assert_eq!(
+            init,
+            InitOptions {
+                bare: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn sparse_update_compares_normalized_paths() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        upstream.commit_file("a/x", "x", "add a").unwrap();
+        upstream.commit_file("b/c/y", "y", "add b/c").unwrap();
+        upstream.commit_file("d/z", "z", "add d").unwrap();
+        let repo = Repo::from_path(dir.path().join("clone"));
+        let options = UpdateOptions {
+            clone: CloneOptions {
+                sparse_paths: Some(vec!["b/c/".to_owned(), "a".to_owned(), "a/".to_owned()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        repo.update_with_options(upstream.url(), &options).unwrap();
+        assert!(repo.path().join("a/x").is_file());
+        assert!(repo.path().join("b/c/y").is_file());
+        assert!(!repo.path().join("d").exists());
+        let listed = repo.sparse_paths().unwrap().unwrap();
+        assert_eq!(listed, ["a", "b/c"]);
+        assert_eq!(
+            normalize_sparse_paths(&listed),
+            normalize_sparse_paths(options.clone.sparse_paths.as_ref().unwrap())
+        );
+
+        upstream.commit_file("d/z", "z2", "change d").unwrap();
+        upstream.commit_file("a/x", "x2", "change a").unwrap();
+        repo.update_with_options(upstream.url(), &options).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.path().join("a/x")).unwrap(),
+            "x2"
+        );
+        assert!(!repo.path().join("d").exists());
+
+        repo.set_sparse_paths(&["d"]).unwrap();
+        assert!(repo.path().join("d/z").is_file());
+        assert!(!repo.path().join("a").exists());
+        assert_eq!(repo.sparse_paths().unwrap().unwrap(), ["d"]);
+    }
+
+    #[test]
+    fn update_pinned_recovers_and_follows_moved_remote() {
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let first = Repo::from_path(upstream.path()).head_hash().unwrap();
+        let old = upstream.push_to_bare(dir.path().join("old.git")).unwrap();
+        let repo = Repo::from_path(dir.path().join("clone"));
+        std::fs::create_dir_all(repo.path().join(".git/objects")).unwrap();
+        let outcome = repo.update_pinned(&old, &first).unwrap();
+        assert!(outcome.freshly_cloned);
+        assert_eq!(outcome.new_head, first);
+
+        // The pinned commit only exists at the new URL.
+        let second = upstream.commit_file("file", "2", "after moving").unwrap();
+        let new = upstream.push_to_bare(dir.path().join("new.git")).unwrap();
+        let outcome = repo.update_pinned(&new, &second).unwrap();
+        assert!(!outcome.freshly_cloned);
+        assert_eq!(outcome.old_head.as_deref(), Some(first.as_str()));
+        assert_eq!(outcome.new_head, second);
+        assert_eq!(repo.remote_url("origin").unwrap(), new.to_str().unwrap());
+
+        let other = Repo::from_path(dir.path().join("not-a-repo"));
+        std::fs::create_dir_all(other.path()).unwrap();
+        std::fs::write(other.path().join("data"), "").unwrap();
+        assert!(matches!(
+            other.update_pinned(&new, &second),
+            Err(Error::NotARepo { .. })
+        ));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_update_and_status_match_their_blocking_versions() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = TempDir::new().unwrap();
+        let upstream = upstream(&dir);
+        let repo = Repo::from_path(dir.path().join("clone"));
+        assert_eq!(
+            runtime.block_on(repo.status_async()).unwrap(),
+            Status::Missing
+        );
+        let outcome = runtime.block_on(repo.update_async(upstream.url())).unwrap();
+        assert!(outcome.freshly_cloned);
+        assert_eq!(
+            runtime.block_on(repo.status_async()).unwrap(),
+            Status::Fresh
+        );
+
+        let second = upstream.commit_file("file", "2", "second").unwrap();
+        assert_eq!(
+            runtime.block_on(repo.status_async()).unwrap(),
+            Status::Behind(1)
+        );
+        let outcome = runtime.block_on(repo.update_async(upstream.url())).unwrap();
+        assert!(!outcome.freshly_cloned);
+        assert_eq!(outcome.new_head, second);
+
+        let bare = Repo::from_path(dir.path().join("bare.git")).with_kind(RepoKind::Bare);
+        let outcome = runtime.block_on(bare.update_async(upstream.url())).unwrap();
+        assert_eq!(outcome.new_head, second);
+        upstream.commit_file("file", "3", "third").unwrap();
+        assert_eq!(
+            runtime.block_on(bare.status_async()).unwrap(),
+            Status::Behind(1)
+        );
+        let outcome = runtime.block_on(bare.update_async(upstream.url())).unwrap();
+        assert_eq!(outcome.old_head.as_deref(), Some(second.as_str()));
     }
 }
@@ -1,4 +1,6 @@
-use crate::Git;
+use crate::{
+    config::Scope, progress::ProgressEvent, CommandExt as _, Git, Limits, Progress, Stopped,
+};
 use once_cell_regex::regex;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -11,15 +13,62 @@ use std::{
 #[derive(Debug)]
 pub enum Source {
     NameMissing,
-    IndexCheckFailed(std::io::Error),
-    InitCheckFailed(std::io::Error),
-    PathInvalidUtf8,
+    IndexCheckFailed(crate::config::Error),
+    InitCheckFailed(crate::config::Error),
     AddFailed(bossy::Error),
     InitFailed(bossy::Error),
+    GaveUp {
+        attempts: u32,
+        source: bossy::Error,
+    },
+    AuthenticationRequired(bossy::Error),
+    Cancelled,
     CheckoutFailed {
         commit: String,
         source: bossy::Error,
     },
+    DeinitFailed(bossy::Error),
+    RmFailed(bossy::Error),
+    ModulesUpdateFailed(bossy::Error),
+    GitLinkRemoveFailed(std::io::Error),
+    GitDirFailed(Box<crate::Error>),
+    ModulesDirRemoveFailed(std::io::Error),
+    RecordedCommitFailed(bossy::Error),
+    CommitCheckFailed(crate::repo::Error),
+    FetchFailed(bossy::Error),
+    CommitUnavailable {
+        commit: String,
+    },
+    UpdateFailed(bossy::Error),
+    UrlCheckFailed(crate::config::Error),
+    SyncFailed(bossy::Error),
+    NotInIndex,
+    StatusFailed(bossy::Error),
+    DirtyCheckFailed(crate::repo::Error),
+    CommitWithBranch {
+        commit: String,
+    },
+    HeadCheckFailed(crate::repo::Error),
+    ShallowConfigFailed(crate::config::Error),
+    UnshallowFailed(crate::repo::Error),
+    ModulesWriteFailed(crate::config::Error),
+    SetUrlFailed {
+        old: String,
+        new: String,
+        source: bossy::Error,
+    },
+    RelocateDestExists {
+        dest: PathBuf,
+    },
+    RelocateFailed {
+        dest: PathBuf,
+        source: bossy::Error,
+    },
+    PathOccupied,
+    MoveAsideFailed {
+        dest: PathBuf,
+        source: std::io::Error,
+    },
 }
 
 #[derive(Debug)]
@@ -46,11 +95,6 @@ impl Display for Error {
                 "Failed to check \".git/config\" for submodule {:?}: {}",
                 self.submodule.name().unwrap(), err,
             ),
-            Source::PathInvalidUtf8 => write!(
-                f,
-                "Submodule path {:?} wasn't valid utf-8.",
-                self.submodule.path,
-            ),
             Source::AddFailed(err) => write!(
                 f,
                 "Failed to add submodule {:?} with remote {:?} and path {:?}: {}",
@@ -61,11 +105,156 @@ impl Display for Error {
                 "Failed to init submodule {:?} with remote {:?} and path {:?}: {}",
                 self.submodule.name().unwrap(), self.submodule.remote, self.submodule.path, err
             ),
+            Source::GaveUp { attempts, source } => write!(
+                f,
+                "Failed to clone submodule {:?} with remote {:?} after {} attempts: {}",
+                self.submodule.name().unwrap(), self.submodule.remote, attempts, source
+            ),
+            Source::AuthenticationRequired(err) => write!(
+                f,
+                "Submodule {:?} with remote {:?} needs credentials, but prompting for them is disabled: {}",
+                self.submodule.name().unwrap(), self.submodule.remote, err
+            ),
+            Source::Cancelled => write!(
+                f,
+                "Cloning submodule {:?} with remote {:?} was cancelled.",
+                self.submodule.name().unwrap(), self.submodule.remote
+            ),
             Source::CheckoutFailed { commit, source } => write!(
                 f,
                 "Failed to checkout commit {:?} from submodule {:?} with remote {:?} and path {:?}: {}",
                 commit, self.submodule.name().unwrap(), self.submodule.remote, self.submodule.path, source
             ),
+            Source::DeinitFailed(err) => write!(
+                f,
+                "Failed to deinit submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::RmFailed(err) => write!(
+                f,
+                "Failed to remove submodule {:?} at path {:?} from the index: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::ModulesUpdateFailed(err) => write!(
+                f,
+                "Failed to remove submodule {:?} from \".gitmodules\": {}",
+                self.submodule.name().unwrap(), err
+            ),
+            Source::GitLinkRemoveFailed(err) => write!(
+                f,
+                "Failed to remove \".git\" file from submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::GitDirFailed(err) => write!(
+                f,
+                "Failed to find the superproject's git directory to delete the repo of submodule {:?}: {}",
+                self.submodule.name().unwrap(), err
+            ),
+            Source::ModulesDirRemoveFailed(err) => write!(
+                f,
+                "Failed to delete repo of submodule {:?} from \".git/modules\": {}",
+                self.submodule.name().unwrap(), err
+            ),
+            Source::RecordedCommitFailed(err) => write!(
+                f,
+                "Failed to get commit recorded for submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::CommitCheckFailed(err) => write!(
+                f,
+                "Failed to check for commit in submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::FetchFailed(err) => write!(
+                f,
+                "Failed to fetch submodule {:?} with remote {:?} and path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.remote, self.submodule.path, err
+            ),
+            Source::CommitUnavailable { commit } => write!(
+                f,
+                "Commit {:?} isn't available from submodule {:?} with remote {:?}, even after fetching.",
+                commit, self.submodule.name().unwrap(), self.submodule.remote
+            ),
+            Source::UpdateFailed(err) => write!(
+                f,
+                "Failed to update submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::UrlCheckFailed(err) => write!(
+                f,
+                "Failed to check \".gitmodules\" for URL of submodule {:?}: {}",
+                self.submodule.name().unwrap(), err
+            ),
+            Source::SyncFailed(err) => write!(
+                f,
+                "Failed to sync URL of submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::NotInIndex => write!(
+                f,
+                "Submodule {:?} at path {:?} isn't in the index.",
+                self.submodule.name().unwrap(), self.submodule.path
+            ),
+            Source::StatusFailed(err) => write!(
+                f,
+                "Failed to get status of submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::DirtyCheckFailed(err) => write!(
+                f,
+                "Failed to check submodule {:?} at path {:?} for changes: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::CommitWithBranch { commit } => write!(
+                f,
+                "Can't check out commit {:?} in submodule {:?}, since it tracks branch {:?}; please specify either a commit or a branch.",
+                commit, self.submodule.name().unwrap(), self.submodule.branch.as_deref().unwrap_or_default()
+            ),
+            Source::HeadCheckFailed(err) => write!(
+                f,
+                "Failed to get checked out commit of submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::ShallowConfigFailed(err) => write!(
+                f,
+                "Failed to mark submodule {:?} as shallow in \".gitmodules\": {}",
+                self.submodule.name().unwrap(), err
+            ),
+            Source::UnshallowFailed(err) => write!(
+                f,
+                "Failed to fetch full history of submodule {:?} at path {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, err
+            ),
+            Source::ModulesWriteFailed(err) => write!(
+                f,
+                "Failed to write submodule {:?} to \".gitmodules\": {}",
+                self.submodule.name().unwrap(), err
+            ),
+            Source::SetUrlFailed { old, new, source } => write!(
+                f,
+                "Failed to change URL of submodule {:?} from {:?} to {:?}: {}",
+                self.submodule.name().unwrap(), old, new, source
+            ),
+            Source::RelocateDestExists { dest } => write!(
+                f,
+                "Can't move submodule {:?} from {:?} to {:?}, since something already exists there.",
+                self.submodule.name().unwrap(), self.submodule.path, dest
+            ),
+            Source::RelocateFailed { dest, source } => write!(
+                f,
+                "Failed to move submodule {:?} from {:?} to {:?}: {}",
+                self.submodule.name().unwrap(), self.submodule.path, dest, source
+            ),
+            Source::PathOccupied => write!(
+                f,
+                "Can't add submodule {:?}, since something other than a submodule already exists at path {:?}; please move it or enable `replace_existing`.",
+                self.submodule.name().unwrap(), self.submodule.path
+            ),
+            Source::MoveAsideFailed { dest, source } => write!(
+                f,
+                "Failed to move existing contents of submodule path {:?} to {:?}: {}",
+                self.submodule.path, dest, source
+            ),
         }
     }
 }
@@ -73,20 +262,164 @@ impl Display for Error {
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match &self.source {
-            Source::NameMissing | Source::PathInvalidUtf8 => None,
+            Source::NameMissing
+            | Source::Cancelled
+            | Source::CommitUnavailable { .. }
+            | Source::NotInIndex
+            | Source::CommitWithBranch { .. }
+            | Source::PathOccupied
+            | Source::RelocateDestExists { .. } => None,
             Source::IndexCheckFailed(err) | Source::InitCheckFailed(err) => Some(err),
-            Source::AddFailed(err) | Source::InitFailed(err) => Some(err),
-            Source::CheckoutFailed { source, .. } => Some(source),
+            Source::AddFailed(err)
+            | Source::InitFailed(err)
+            | Source::AuthenticationRequired(err) => Some(err),
+            Source::GaveUp { source, .. } | Source::CheckoutFailed { source, .. } => Some(source),
+            Source::DeinitFailed(err)
+            | Source::RmFailed(err)
+            | Source::ModulesUpdateFailed(err) => Some(err),
+            Source::GitLinkRemoveFailed(err) | Source::ModulesDirRemoveFailed(err) => Some(err),
+            Source::GitDirFailed(err) => Some(err.as_ref()),
+            Source::MoveAsideFailed { source, .. } => Some(source),
+            Source::SetUrlFailed { source, .. } | Source::RelocateFailed { source, .. } => {
+                Some(source)
+            }
+            Source::RecordedCommitFailed(err)
+            | Source::FetchFailed(err)
+            | Source::UpdateFailed(err)
+            | Source::SyncFailed(err)
+            | Source::StatusFailed(err) => Some(err),
+            Source::CommitCheckFailed(err)
+            | Source::DirtyCheckFailed(err)
+            | Source::HeadCheckFailed(err)
+            | Source::UnshallowFailed(err) => Some(err),
+            Source::UrlCheckFailed(err)
+            | Source::ShallowConfigFailed(err)
+            | Source::ModulesWriteFailed(err) => Some(err),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemoveOptions {
+    /// Leave the submodule's files in place as untracked files rather than
+    /// deleting them.
+    pub keep_working_tree: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpdateOptions {
+    /// Check out the latest commit on the submodule's remote-tracking branch
+    /// instead of the commit recorded in the superproject.
+    pub remote: bool,
+    /// Update the submodule's own submodules too.
+    pub recursive: bool,
+}
+
+/// One of the things [`Submodule::init`] will do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitStep {
+    /// Move whatever's at the path to `<path>.orig`.
+    MoveAside,
+    /// Add the submodule to `.gitmodules` and the index.
+    Add,
+    /// Change the URL in `.gitmodules` from `recorded` to ours.
+    UpdateUrl {
+        recorded: Option<String>,
+    },
+    /// Clone the submodule and check out the recorded commit.
+    Initialize,
+    Checkout {
+        commit: String,
+    },
+}
+
+/// What [`Submodule::init`] will do, in order.
+#[derive(Clone, Debug)]
+pub struct InitPlan {
+    pub name: String,
+    pub path: PathBuf,
+    pub steps: Vec<InitStep>,
+}
+
+impl Display for InitPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.steps.is_empty() {
+            return write!(f, "submodule {:?} is up to date", self.name);
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match step {
+                InitStep::MoveAside => write!(f, "will move existing {:?} aside", self.path),
+                InitStep::Add => write!(f, "will add submodule {:?} at {:?}", self.name, self.path),
+                InitStep::UpdateUrl { recorded } => write!(
+                    f,
+                    "will update URL of submodule {:?} (currently {:?})",
+                    self.name, recorded
+                ),
+                InitStep::Initialize => write!(f, "will initialize submodule {:?}", self.name),
+                InitStep::Checkout { commit } => write!(
+                    f,
+                    "will checkout commit {:?} in submodule {:?}",
+                    commit, self.name
+                ),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+impl InitPlan {
+    /// `true` if there's nothing to do.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
 #[derive(Clone, Debug)]
+pub struct SubmoduleStatus {
+    /// The commit the superproject records for the submodule.
+    pub recorded_commit: String,
+    /// The commit checked out in the submodule, or `None` if it isn't
+    /// initialized or has merge conflicts.
+    pub checked_out_commit: Option<String>,
+    pub initialized: bool,
+    /// Whether the submodule's working tree has uncommitted changes.
+    pub dirty: bool,
+}
+
+impl SubmoduleStatus {
+    /// Whether the submodule has the recorded commit checked out.
+    pub fn in_sync(&self) -> bool {
+        self.checked_out_commit.as_deref() == Some(self.recorded_commit.as_str())
+    }
+}
+
+/// Submodules are equal if all of their explicit settings are; a name
+/// inferred from the remote doesn't count, so a submodule with an explicit
+/// name never equals one without.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Submodule {
     name: Option<String>,
     remote: String,
     path: PathBuf,
+    branch: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    shallow: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    replace_existing: bool,
+}
+
+/// i.e. "my-sdk (git@github.com:org/my-sdk.git -> deps/my-sdk)"
+impl Display for Submodule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} ({} -> {})", name, self.remote, self.path.display()),
+            None => write!(f, "{} -> {}", self.remote, self.path.display()),
+        }
+    }
 }
 
 impl Submodule {
@@ -95,102 +428,1402 @@ impl Submodule {
             name: None,
             remote: remote.into(),
             path: path.into(),
+            branch: None,
+            shallow: false,
+            replace_existing: false,
+        }
+    }
+
+    /// Groups `submodule.<name>.<var>` config entries by name, skipping
+    /// submodules without both a URL and a path.
+    pub(crate) fn from_config(entries: Vec<(String, String)>) -> Vec<Self> {
+        let mut submodules = Vec::<Self>::new();
+        for (key, value) in entries {
+            // Names can contain dots, but variable names can't.
+            let (name, var) = match key
+                .strip_prefix("submodule.")
+                .and_then(|key| key.rsplit_once('.'))
+            {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let index = match submodules
+                .iter()
+                .position(|submodule| submodule.name.as_deref() == Some(name))
+            {
+                Some(index) => index,
+                None => {
+                    submodules.push(Self::with_remote_and_path("", "").with_name(name));
+                    submodules.len() - 1
+                }
+            };
+            let submodule = &mut submodules[index];
+            match var {
+                "url" => submodule.remote = value,
+                "path" => submodule.path = value.into(),
+                "branch" => submodule.branch = Some(value),
+                "shallow" => submodule.shallow = value == "true",
+                _ => (),
+            }
         }
+        submodules.retain(|submodule| {
+            !submodule.remote.is_empty() && !submodule.path.as_os_str().is_empty()
+        });
+        submodules
+    }
+
+    /// Use `name` rather than inferring one from the remote.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) -> &mut Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Track `branch` rather than a fixed commit.
+    pub fn with_branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = Some(branch.into());
+        self
     }
 
     pub fn name(&self) -> Option<&str> {
         self.name.as_deref().or_else(|| {
-            let name = regex!(r"(?P<name>\w+)\.git")
+            // The last path segment, minus any `.git` suffix or trailing
+            // slashes. The segment can follow a `:` for scp-like URLs, i.e.
+            // `git@github.com:org/repo`.
+            let name = regex!(r"(?:^|[/:])(?P<name>[\w.-]+?)(?:\.git)?/*$")
                 .captures(&self.remote)
                 // Indexing would return `str` instead of `&str`, which doesn't
                 // play nice with our lifetime needs here...
-                .map(|caps| caps.name("name").unwrap().as_str());
+                .map(|caps| caps.name("name").unwrap().as_str())
+                // A lone `.git` or `..` isn't a name.
+                .filter(|name| *name != ".git" && !name.chars().all(|c| c == '.'));
             log::info!("detected submodule name: {:?}", name);
             name
         })
     }
 
+    pub fn remote(&self) -> &str {
+        &self.remote
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    fn in_index(&self, git: Git<'_>, name: &str) -> std::io::Result<bool> {
-        git.modules().map(|modules| {
-            modules
-                .filter(|modules| modules.contains(&format!("[submodule {:?}]", name)))
-                .is_some()
-        })
+    pub fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    /// Clone the submodule with a depth of 1, since we don't need its
+    /// history. This is recorded in `.gitmodules` so later updates stay
+    /// shallow too.
+    pub fn with_shallow(mut self, shallow: bool) -> Self {
+        self.shallow = shallow;
+        self
     }
 
-    fn initialized(&self, git: Git<'_>, name: &str) -> std::io::Result<bool> {
-        git.config().map(|config| {
-            config
-                .filter(|config| config.contains(&format!("[submodule {:?}]", name)))
-                .is_some()
+    pub fn shallow(&self) -> bool {
+        self.shallow
+    }
+
+    /// If something other than a submodule is already at the path, move it
+    /// aside to `<path>.orig` instead of failing with
+    /// [`Source::PathOccupied`].
+    pub fn with_replace_existing(mut self, replace_existing: bool) -> Self {
+        self.replace_existing = replace_existing;
+        self
+    }
+
+    pub fn replace_existing(&self) -> bool {
+        self.replace_existing
+    }
+
+    /// Whether anything but an empty directory is at the path.
+    fn occupied(&self, git: Git<'_>) -> bool {
+        let path = git.root().join(&self.path);
+        let empty = path.is_dir()
+            && path
+                .read_dir()
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or_default();
+        path.exists() && !empty
+    }
+
+    /// `submodule add` refuses to clone into anything that already exists at
+    /// the path, even an empty directory.
+    fn remove_if_empty(&self, git: Git<'_>) {
+        let path = git.root().join(&self.path);
+        if path.is_dir() && !self.occupied(git) {
+            std::fs::remove_dir(&path).ok();
+        }
+    }
+
+    fn move_aside(&self, git: Git<'_>) -> Result<(), Error> {
+        let path = git.root().join(&self.path);
+        let mut dest = path.as_os_str().to_owned();
+        dest.push(".orig");
+        let mut dest = PathBuf::from(dest);
+        let mut suffix = 1;
+        while dest.exists() {
+            let mut numbered = path.as_os_str().to_owned();
+            numbered.push(format!(".orig.{}", suffix));
+            dest = numbered.into();
+            suffix += 1;
+        }
+        log::info!("moving existing {:?} to {:?}", path, dest);
+        std::fs::rename(&path, &dest).map_err(|source| Error {
+            submodule: self.clone(),
+            source: Source::MoveAsideFailed { dest, source },
         })
     }
 
+    fn in_index(&self, git: Git<'_>, name: &str) -> Result<bool, crate::config::Error> {
+        if !git.root().join(".gitmodules").is_file() {
+            return Ok(false);
+        }
+        #[cfg(feature = "gix")]
+        if let Some(in_index) = crate::native::in_index(git, name) {
+            return Ok(in_index);
+        }
+        // We only care whether the path is set, which is just as well, since
+        // it isn't necessarily valid UTF-8.
+        let key = format!("submodule.{}.path", name);
+        match git
+            .command()
+            .with_arg("config")
+            .with_args(Scope::Modules.args())
+            .with_arg("--get")
+            .with_arg(&key)
+            .run_and_wait_for_output()
+        {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == Some(1) => Ok(false),
+            Err(source) => Err(crate::config::Error::GetFailed { key, source }),
+        }
+    }
+
+    fn initialized(&self, git: Git<'_>, name: &str) -> Result<bool, crate::config::Error> {
+        #[cfg(feature = "gix")]
+        if let Some(initialized) = crate::native::initialized(git, name) {
+            return Ok(initialized);
+        }
+        git.config_get(&format!("submodule.{}.url", name), Scope::Local)
+            .map(|url| url.is_some())
+    }
+
+    /// Adds the submodule to the index if it isn't there already, then
+    /// initializes and checks it out (along with its own submodules). Other
+    /// submodules in the superproject are left alone.
     pub fn init(&self, git: Git<'_>, commit: Option<&str>) -> Result<(), Error> {
+        self.init_inner(git, commit, false, None)
+    }
+
+    /// The same as [`Submodule::init`], but without blocking the async
+    /// runtime. Clones are waited for without tying up a thread; the other
+    /// steps run on tokio's blocking pool. Dropping the future kills whatever
+    /// git command is running, which fails with [`Source::Cancelled`].
+    ///
+    /// This needs to be polled from within a tokio runtime with its IO and
+    /// time drivers enabled.
+    #[cfg(feature = "tokio")]
+    pub async fn init_async(&self, git: Git<'_>, commit: Option<&str>) -> Result<(), Error> {
+        let token = crate::CancellationToken::new();
+        // The task needs everything owned, so we carry `git`'s settings over
+        // in a `Repo`.
+        let superproject = crate::repo::Repo::from_path(git.root())
+            .with_settings_of(git.with_cancellation(Some(&token)));
+        let submodule = self.clone();
+        let commit = commit.map(ToOwned::to_owned);
+        crate::spawn_cancellable(token, async move {
+            let plan = {
+                let commit = commit.clone();
+                on_blocking_pool(&submodule, &superproject, move |submodule, git| {
+                    submodule.plan(git, commit.as_deref())
+                })
+                .await?
+            };
+            if plan.is_empty() {
+                log::info!("submodule already initialized: {:#?}", submodule);
+            }
+            for step in plan.steps {
+                let name = plan.name.clone();
+                match step {
+                    InitStep::Add => {
+                        let mut command =
+                            on_blocking_pool(&submodule, &superproject, move |submodule, git| {
+                                submodule.add_command(git, &name, false)
+                            })
+                            .await;
+                        submodule
+                            .clone_with_async(superproject.git(), &mut command, Source::AddFailed)
+                            .await?;
+                        let name = plan.name.clone();
+                        on_blocking_pool(&submodule, &superproject, move |submodule, git| {
+                            submodule.finish_add(git, &name)
+                        })
+                        .await?;
+                    }
+                    InitStep::Initialize => {
+                        let mut command =
+                            submodule.initialize_command(superproject.git(), false, false);
+                        submodule
+                            .clone_with_async(superproject.git(), &mut command, Source::InitFailed)
+                            .await?;
+                    }
+                    step => {
+                        on_blocking_pool(&submodule, &superproject, move |submodule, git| {
+                            submodule.run_step(git, &name, &step, false, None)
+                        })
+                        .await?
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// The same as [`Submodule::init`], but calls `progress` as the
+    /// submodule is cloned.
+    pub fn init_with_progress(
+        &self,
+        git: Git<'_>,
+        commit: Option<&str>,
+        mut progress: impl FnMut(ProgressEvent),
+    ) -> Result<(), Error> {
+        self.init_inner(git, commit, false, Some(&mut progress))
+    }
+
+    /// The same as [`Submodule::init`], but initializes every submodule in the
+    /// superproject rather than just this one.
+    pub fn init_all(&self, git: Git<'_>, commit: Option<&str>) -> Result<(), Error> {
+        self.init_inner(git, commit, true, None)
+    }
+
+    /// Works out what [`Submodule::init`] would do, without changing
+    /// anything.
+    pub fn plan(&self, git: Git<'_>, commit: Option<&str>) -> Result<InitPlan, Error> {
         let name = self.name().ok_or_else(|| Error {
             submodule: self.clone(),
             source: Source::NameMissing,
         })?;
-        let in_index = self.in_index(git, &name).map_err(|source| Error {
+        if let Some(commit) = commit.filter(|_| self.branch.is_some()) {
+            return Err(Error {
+                submodule: self.clone(),
+                source: Source::CommitWithBranch {
+                    commit: commit.to_owned(),
+                },
+            });
+        }
+        let mut steps = Vec::new();
+        let in_index = self.in_index(git, name).map_err(|source| Error {
             submodule: self.clone(),
             source: Source::IndexCheckFailed(source),
         })?;
         let initialized = if !in_index {
-            let path_str = self.path.to_str().ok_or_else(|| Error {
-                submodule: self.clone(),
-                source: Source::PathInvalidUtf8,
-            })?;
-            log::info!("adding submodule: {:#?}", self);
-            git.command()
-                .with_args(&["submodule", "add", "--name", &name, &self.remote, path_str])
-                .run_and_wait()
-                .map_err(|source| Error {
-                    submodule: self.clone(),
-                    source: Source::AddFailed(source),
-                })?;
+            if self.occupied(git) {
+                if !self.replace_existing {
+                    return Err(Error {
+                        submodule: self.clone(),
+                        source: Source::PathOccupied,
+                    });
+                }
+                steps.push(InitStep::MoveAside);
+            }
+            steps.push(InitStep::Add);
             false
         } else {
-            log::info!("submodule already in index: {:#?}", self);
-            self.initialized(git, &name).map_err(|source| Error {
+            let recorded = self.recorded_url(git, name)?;
+            if recorded.as_deref() != Some(self.remote.as_str()) {
+                steps.push(InitStep::UpdateUrl { recorded });
+            }
+            // Linked worktrees share the config (and so the URL) with the
+            // main worktree, but each needs its own checkout.
+            self.initialized(git, name).map_err(|source| Error {
                 submodule: self.clone(),
                 source: Source::InitCheckFailed(source),
-            })?
+            })? && git.root().join(&self.path).join(".git").exists()
         };
         if !initialized {
-            log::info!("initializing submodule: {:#?}", self);
+            steps.push(InitStep::Initialize);
+        }
+        if let Some(commit) = commit {
+            let checked_out = initialized
+                && self.recorded_commit(git)?.as_deref() == Some(commit)
+                && crate::repo::Repo::from_path(git.root().join(&self.path))
+                    .with_settings_of(git)
+                    .head_hash()
+                    .map_err(|source| Error {
+                        submodule: self.clone(),
+                        source: Source::HeadCheckFailed(source),
+                    })?
+                    == commit;
+            if !checked_out {
+                steps.push(InitStep::Checkout {
+                    commit: commit.to_owned(),
+                });
+            }
+        }
+        Ok(InitPlan {
+            name: name.to_owned(),
+            path: self.path.clone(),
+            steps,
+        })
+    }
+
+    fn init_inner(
+        &self,
+        git: Git<'_>,
+        commit: Option<&str>,
+        all: bool,
+        mut progress: Progress<'_>,
+    ) -> Result<(), Error> {
+        let plan = self.plan(git, commit)?;
+        if plan.is_empty() {
+            log::info!("submodule already initialized: {:#?}", self);
+        }
+        for step in &plan.steps {
+            self.run_step(git, &plan.name, step, all, crate::reborrow(&mut progress))?;
+        }
+        Ok(())
+    }
+
+    fn run_step(
+        &self,
+        git: Git<'_>,
+        name: &str,
+        step: &InitStep,
+        all: bool,
+        mut progress: Progress<'_>,
+    ) -> Result<(), Error> {
+        match step {
+            InitStep::MoveAside => self.move_aside(git)?,
+            InitStep::Add => self.add(git, name, progress)?,
+            InitStep::UpdateUrl { recorded } => self.update_url(git, name, recorded.as_deref())?,
+            InitStep::Initialize => {
+                let mut command = self.initialize_command(git, all, progress.is_some());
+                self.clone_with(
+                    git,
+                    &mut command,
+                    crate::reborrow(&mut progress),
+                    Source::InitFailed,
+                )?;
+            }
+            InitStep::Checkout { commit } => {
+                let path = git.root().join(self.path());
+                log::info!(
+                    "checking out commit {:?} in submodule at {:?}",
+                    commit,
+                    path
+                );
+                if self.shallow {
+                    self.deepen_for(git, &path, commit)?;
+                }
+                self.ensure_commit(git, &path, commit)?;
+                git.at(&path)
+                    .command()
+                    .with_args(["checkout", commit])
+                    .run_and_wait_in(git.output_mode())
+                    .map_err(|source| Error {
+                        submodule: self.clone(),
+                        source: Source::CheckoutFailed {
+                            commit: commit.to_owned(),
+                            source,
+                        },
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `submodule update --init`, for just this submodule unless `all` is
+    /// set.
+    fn initialize_command(&self, git: Git<'_>, all: bool, progress: bool) -> bossy::Command {
+        log::info!("initializing submodule: {:#?}", self);
+        let mut command = git.command();
+        command.add_parsed_args("submodule update --init --recursive");
+        if progress {
+            command.add_arg("--progress");
+        }
+        if self.shallow {
+            command.add_args(["--depth", "1"]);
+        }
+        if !all {
+            command.add_arg("--").add_arg(&self.path);
+        }
+        command
+    }
+
+    fn add(&self, git: Git<'_>, name: &str, progress: Progress<'_>) -> Result<(), Error> {
+        let mut command = self.add_command(git, name, progress.is_some());
+        self.clone_with(git, &mut command, progress, Source::AddFailed)?;
+        self.finish_add(git, name)
+    }
+
+    fn add_command(&self, git: Git<'_>, name: &str, progress: bool) -> bossy::Command {
+        self.remove_if_empty(git);
+        log::info!("adding submodule: {:#?}", self);
+        let mut command = git.command();
+        command.add_args(["submodule", "add", "--name", name]);
+        if progress {
+            command.add_arg("--progress");
+        }
+        if let Some(branch) = &self.branch {
+            command.add_arg("-b").add_arg(branch);
+        }
+        if self.shallow {
+            command.add_args(["--depth", "1"]);
+        }
+        command.add_arg(&self.remote).add_arg(&self.path);
+        command
+    }
+
+    /// Does what's left of `add` once the submodule's been cloned.
+    fn finish_add(&self, git: Git<'_>, name: &str) -> Result<(), Error> {
+        if self.shallow {
+            git.config_set(
+                &format!("submodule.{}.shallow", name),
+                "true",
+                Scope::Modules,
+            )
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::ShallowConfigFailed(source),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Runs `command`, which clones the submodule, retrying it according to
+    /// `git`'s retry policy.
+    fn clone_with(
+        &self,
+        git: Git<'_>,
+        command: &mut bossy::Command,
+        progress: Progress<'_>,
+        failed: fn(bossy::Error) -> Source,
+    ) -> Result<(), Error> {
+        let result = command.run_and_wait_within(git.output_mode(), progress, &clone_limits(git));
+        self.cloned(result, failed)
+    }
+
+    /// The same as [`Submodule::clone_with`], but without blocking the async
+    /// runtime.
+    #[cfg(feature = "tokio")]
+    async fn clone_with_async(
+        &self,
+        git: Git<'_>,
+        command: &mut bossy::Command,
+        failed: fn(bossy::Error) -> Source,
+    ) -> Result<(), Error> {
+        let result =
+            crate::run_and_wait_within_async(command, git.output_mode(), &clone_limits(git)).await;
+        self.cloned(result, failed)
+    }
+
+    /// Checks how a clone went, failing with `failed` if git did.
+    fn cloned(
+        &self,
+        result: Result<bossy::Result<()>, Stopped>,
+        failed: fn(bossy::Error) -> Source,
+    ) -> Result<(), Error> {
+        let source = match result {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(source)) => failed(source),
+            Err(Stopped::GaveUp { attempts, source }) => Source::GaveUp { attempts, source },
+            Err(Stopped::AuthenticationRequired(source)) => Source::AuthenticationRequired(source),
+            Err(Stopped::Cancelled) => Source::Cancelled,
+            Err(stopped @ Stopped::TimedOut(_)) => unreachable!(
+                "developer error: submodule clone timed out without a timeout: {:?}",
+                stopped
+            ),
+        };
+        Err(Error {
+            submodule: self.clone(),
+            source,
+        })
+    }
+
+    /// Does everything `add` does except cloning, which is left
+    /// to `submodule update --init`. The index needs a commit to record, so
+    /// we ask the remote for the tip of our branch (or its `HEAD`).
+    fn register(&self, git: Git<'_>, name: &str) -> Result<(), Error> {
+        self.remove_if_empty(git);
+        log::info!("registering submodule: {:#?}", self);
+        let rev = self
+            .branch
+            .as_deref()
+            .map(|branch| format!("refs/heads/{}", branch))
+            .unwrap_or_else(|| "HEAD".to_owned());
+        let commit = git
+            .command()
+            .with_arg("ls-remote")
+            .with_args([&self.remote, &rev])
+            .run_and_wait_for_str(|s| s.split_whitespace().next().map(ToOwned::to_owned))
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::AddFailed(source),
+            })?
+            .ok_or_else(|| Error {
+                submodule: self.clone(),
+                source: Source::CommitUnavailable {
+                    commit: rev.clone(),
+                },
+            })?;
+        let mut entries = vec![
+            ("path", self.path.as_os_str()),
+            ("url", self.remote.as_ref()),
+        ];
+        if let Some(branch) = &self.branch {
+            entries.push(("branch", branch.as_ref()));
+        }
+        if self.shallow {
+            entries.push(("shallow", "true".as_ref()));
+        }
+        for (var, value) in entries {
+            git.config_set(
+                &format!("submodule.{}.{}", name, var),
+                value,
+                Scope::Modules,
+            )
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::ModulesWriteFailed(source),
+            })?;
+        }
+        git.command()
+            .with_parsed_args("add -- .gitmodules")
+            .run_and_wait_for_output()
+            .and_then(|_| {
+                git.command()
+                    .with_parsed_args("update-index --add --cacheinfo")
+                    .with_arg({
+                        let mut cacheinfo = std::ffi::OsString::from(format!("160000,{},", commit));
+                        cacheinfo.push(&self.path);
+                        cacheinfo
+                    })
+                    .run_and_wait_for_output()
+            })
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::AddFailed(source),
+            })?;
+        Ok(())
+    }
+
+    /// Carries out everything in the plan but the initialization for the free
+    /// function [`init_parallel`], returning whether that's still needed.
+    fn prepare(&self, git: Git<'_>) -> Result<bool, Error> {
+        let plan = self.plan(git, None)?;
+        let mut needs_init = false;
+        for step in &plan.steps {
+            match step {
+                InitStep::MoveAside => self.move_aside(git)?,
+                // Relative URLs are resolved against the superproject's
+                // remote, which `submodule add` knows how to do and we don't.
+                InitStep::Add
+                    if self.remote.starts_with("./") || self.remote.starts_with("../") =>
+                {
+                    self.add(git, &plan.name, None)?
+                }
+                InitStep::Add => self.register(git, &plan.name)?,
+                InitStep::UpdateUrl { recorded } => {
+                    self.update_url(git, &plan.name, recorded.as_deref())?
+                }
+                InitStep::Initialize => needs_init = true,
+                InitStep::Checkout { .. } => (),
+            }
+        }
+        Ok(needs_init)
+    }
+
+    /// Removes the submodule from the superproject, which in git means
+    /// deiniting it, removing it from the index and `.gitmodules`, and
+    /// deleting its repo from `.git/modules`. This succeeds without doing
+    /// anything if the submodule isn't in the index.
+    pub fn remove(&self, git: Git<'_>, options: &RemoveOptions) -> Result<(), Error> {
+        let name = self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        let in_index = self.in_index(git, name).map_err(|source| Error {
+            submodule: self.clone(),
+            source: Source::IndexCheckFailed(source),
+        })?;
+        if !in_index {
+            log::info!("submodule not in index: {:#?}", self);
+            return Ok(());
+        }
+        log::info!("removing submodule: {:#?}", self);
+        if options.keep_working_tree {
+            // `deinit` would empty the working tree, so we only drop the
+            // config it would've dropped.
+            let initialized = self.initialized(git, name).map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::InitCheckFailed(source),
+            })?;
+            if initialized {
+                git.command()
+                    .with_parsed_args("config --local --remove-section")
+                    .with_arg(format!("submodule.{}", name))
+                    .run_and_wait_for_output()
+                    .map_err(|source| Error {
+                        submodule: self.clone(),
+                        source: Source::DeinitFailed(source),
+                    })?;
+            }
+            git.command()
+                .with_parsed_args("rm -q --cached --")
+                .with_arg(&self.path)
+                .run_and_wait_for_output()
+                .map_err(|source| Error {
+                    submodule: self.clone(),
+                    source: Source::RmFailed(source),
+                })?;
+            // Unlike a plain `rm`, `rm --cached` leaves `.gitmodules` alone.
             git.command()
-                .with_parsed_args("submodule update --init --recursive")
-                .run_and_wait()
+                .with_parsed_args("config --file .gitmodules --remove-section")
+                .with_arg(format!("submodule.{}", name))
+                .run_and_wait_for_output()
+                .and_then(|_| {
+                    git.command()
+                        .with_parsed_args("add -- .gitmodules")
+                        .run_and_wait_for_output()
+                })
                 .map_err(|source| Error {
                     submodule: self.clone(),
-                    source: Source::InitFailed(source),
+                    source: Source::ModulesUpdateFailed(source),
+                })?;
+            let git_link = git.root().join(&self.path).join(".git");
+            if git_link.is_file() {
+                std::fs::remove_file(&git_link).map_err(|source| Error {
+                    submodule: self.clone(),
+                    source: Source::GitLinkRemoveFailed(source),
                 })?;
+            }
         } else {
-            log::info!("submodule already initalized: {:#?}", self);
+            git.command()
+                .with_parsed_args("submodule deinit -q -f --")
+                .with_arg(&self.path)
+                .run_and_wait_for_output()
+                .map_err(|source| Error {
+                    submodule: self.clone(),
+                    source: Source::DeinitFailed(source),
+                })?;
+            git.command()
+                .with_parsed_args("rm -q -f --")
+                .with_arg(&self.path)
+                .run_and_wait_for_output()
+                .map_err(|source| Error {
+                    submodule: self.clone(),
+                    source: Source::RmFailed(source),
+                })?;
         }
-        if let Some(commit) = commit {
-            let path = git.root().join(self.path());
-            log::info!(
-                "checking out commit {:?} in submodule at {:?}",
-                commit,
-                path
-            );
-            Git::new(&path)
-                .command()
-                .with_args(&["checkout", commit])
-                .run_and_wait()
+        // Each linked worktree clones its submodules into its own git
+        // directory.
+        let modules_dir = git
+            .git_dir()
+            .map(|git_dir| git_dir.join("modules").join(name))
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::GitDirFailed(Box::new(source)),
+            })?;
+        if modules_dir.is_dir() {
+            std::fs::remove_dir_all(&modules_dir).map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::ModulesDirRemoveFailed(source),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The commit the superproject's index records for this submodule, or
+    /// `None` if its path isn't a gitlink.
+    pub fn recorded_commit(&self, git: Git<'_>) -> Result<Option<String>, Error> {
+        self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        git.command()
+            .with_parsed_args("ls-files --stage --")
+            .with_arg(&self.path)
+            .run_and_wait_for_str(|s| {
+                s.lines().find_map(|line| {
+                    // i.e. "160000 <hash> 0\t<path>"
+                    let mut fields = line.split_whitespace();
+                    (fields.next() == Some("160000"))
+                        .then(|| fields.next())
+                        .flatten()
+                        .map(ToOwned::to_owned)
+                })
+            })
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::RecordedCommitFailed(source),
+            })
+    }
+
+    /// Fetches the submodule's full history if its shallow history doesn't
+    /// contain `commit`.
+    fn deepen_for(&self, git: Git<'_>, path: &Path, commit: &str) -> Result<(), Error> {
+        let repo = crate::repo::Repo::from_path(path).with_settings_of(git);
+        let contains_commit = repo.contains_commit(commit).map_err(|source| Error {
+            submodule: self.clone(),
+            source: Source::CommitCheckFailed(source),
+        })?;
+        if !contains_commit {
+            log::info!("deepening submodule at {:?} for commit {:?}", path, commit);
+            repo.unshallow().map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::UnshallowFailed(source),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Fetches inside the submodule if `commit` isn't already present there.
+    fn ensure_commit(&self, git: Git<'_>, path: &Path, commit: &str) -> Result<(), Error> {
+        let repo = crate::repo::Repo::from_path(path).with_settings_of(git);
+        let contains_commit = |repo: &crate::repo::Repo| {
+            repo.contains_commit(commit).map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::CommitCheckFailed(source),
+            })
+        };
+        if contains_commit(&repo)? {
+            return Ok(());
+        }
+        log::info!("fetching submodule at {:?} for commit {:?}", path, commit);
+        // Not every server lets us fetch a commit by hash, so we fall back
+        // to fetching everything.
+        let fetched_directly = repo
+            .git()
+            .command()
+            .with_args(["fetch", "origin", commit])
+            .run_and_wait_for_output()
+            .is_ok();
+        if !fetched_directly || !contains_commit(&repo)? {
+            repo.git()
+                .command_parse("fetch origin")
+                .run_and_wait_in(git.output_mode())
                 .map_err(|source| Error {
                     submodule: self.clone(),
-                    source: Source::CheckoutFailed {
-                        commit: commit.to_owned(),
-                        source,
-                    },
+                    source: Source::FetchFailed(source),
                 })?;
         }
+        if contains_commit(&repo)? {
+            Ok(())
+        } else {
+            Err(Error {
+                submodule: self.clone(),
+                source: Source::CommitUnavailable {
+                    commit: commit.to_owned(),
+                },
+            })
+        }
+    }
+
+    /// Checks out the commit the superproject records for this submodule (or
+    /// the tip of its branch with [`UpdateOptions::remote`]), without
+    /// touching any other submodules.
+    pub fn update(&self, git: Git<'_>, options: &UpdateOptions) -> Result<(), Error> {
+        self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        let path = git.root().join(&self.path);
+        if !options.remote && path.join(".git").exists() {
+            if let Some(commit) = self.recorded_commit(git)? {
+                self.ensure_commit(git, &path, &commit)?;
+            }
+        }
+        log::info!("updating submodule: {:#?}", self);
+        let mut command = git.command();
+        command.add_parsed_args("submodule update --checkout");
+        if options.remote {
+            command.add_arg("--remote");
+        }
+        if options.recursive {
+            command.add_arg("--recursive");
+        }
+        command
+            .with_arg("--")
+            .with_arg(&self.path)
+            .run_and_wait_in(git.output_mode())
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::UpdateFailed(source),
+            })?;
+        Ok(())
+    }
+
+    fn recorded_url(&self, git: Git<'_>, name: &str) -> Result<Option<String>, Error> {
+        git.config_get(&format!("submodule.{}.url", name), Scope::Modules)
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::UrlCheckFailed(source),
+            })
+    }
+
+    /// Points `.gitmodules` at our URL instead of `recorded` and syncs.
+    fn update_url(&self, git: Git<'_>, name: &str, recorded: Option<&str>) -> Result<(), Error> {
+        log::info!(
+            "submodule {:?} is recorded with URL {:?} rather than {:?}; updating",
+            name,
+            recorded,
+            self.remote
+        );
+        self.set_url(git, self.remote.clone()).map(|_| ())
+    }
+
+    /// Points the submodule at `new_url` in `.gitmodules` and syncs,
+    /// returning the updated submodule. This is what `submodule set-url`
+    /// does, but that doesn't exist before git 2.25.
+    pub fn set_url(&self, git: Git<'_>, new_url: impl Into<String>) -> Result<Self, Error> {
+        let name = self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        let new_url = new_url.into();
+        git.command()
+            .with_parsed_args("config --file .gitmodules")
+            .with_arg(format!("submodule.{}.url", name))
+            .with_arg(&new_url)
+            .run_and_wait_for_output()
+            .and_then(|_| {
+                git.command()
+                    .with_parsed_args("add -- .gitmodules")
+                    .run_and_wait_for_output()
+            })
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::SetUrlFailed {
+                    old: self.remote.clone(),
+                    new: new_url.clone(),
+                    source,
+                },
+            })?;
+        let updated = Self {
+            remote: new_url,
+            ..self.clone()
+        };
+        updated.sync(git)?;
+        Ok(updated)
+    }
+
+    /// Moves the submodule to `new_path`, which git also records in
+    /// `.gitmodules`, returning the updated submodule.
+    pub fn relocate(&self, git: Git<'_>, new_path: impl Into<PathBuf>) -> Result<Self, Error> {
+        self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        let new_path = new_path.into();
+        if git.root().join(&new_path).exists() {
+            return Err(Error {
+                submodule: self.clone(),
+                source: Source::RelocateDestExists { dest: new_path },
+            });
+        }
+        // `mv` won't create missing parent directories, but if we can't
+        // either, then it'll tell us why.
+        if let Some(parent) = git.root().join(&new_path).parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        git.command()
+            .with_parsed_args("mv --")
+            .with_arg(&self.path)
+            .with_arg(&new_path)
+            .run_and_wait_for_output()
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::RelocateFailed {
+                    dest: new_path.clone(),
+                    source,
+                },
+            })?;
+        Ok(Self {
+            path: new_path,
+            ..self.clone()
+        })
+    }
+
+    /// Copies the submodule's URL from `.gitmodules` into the superproject's
+    /// config and the submodule's own remote.
+    pub fn sync(&self, git: Git<'_>) -> Result<(), Error> {
+        self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        git.command()
+            .with_parsed_args("submodule sync --")
+            .with_arg(&self.path)
+            .run_and_wait_for_output()
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::SyncFailed(source),
+            })?;
         Ok(())
     }
+
+    pub fn status(&self, git: Git<'_>) -> Result<SubmoduleStatus, Error> {
+        self.name().ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NameMissing,
+        })?;
+        let recorded_commit = self.recorded_commit(git)?.ok_or_else(|| Error {
+            submodule: self.clone(),
+            source: Source::NotInIndex,
+        })?;
+        // i.e. "+<hash> <path> (<describe>)", where the prefix is `-` if the
+        // submodule isn't initialized, `+` if the checked out commit differs
+        // from the recorded one, and `U` if there are merge conflicts.
+        let (prefix, commit) = git
+            .command()
+            .with_parsed_args("submodule status --")
+            .with_arg(&self.path)
+            .run_and_wait_for_output()
+            .map(|output| {
+                // The path isn't necessarily valid UTF-8, but everything before
+                // it is.
+                let line = output.stdout();
+                let prefix = line.first().copied().map(char::from);
+                let commit = line
+                    .get(1..)
+                    .unwrap_or_default()
+                    .split(|byte| *byte == b' ')
+                    .next()
+                    .unwrap_or_default();
+                (prefix, String::from_utf8_lossy(commit).into_owned())
+            })
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::StatusFailed(source),
+            })?;
+        let initialized = prefix != Some('-');
+        let (checked_out_commit, dirty) = match prefix {
+            Some(' ') | Some('+') => {
+                let dirty = crate::repo::Repo::from_path(git.root().join(&self.path))
+                    .with_settings_of(git)
+                    .is_dirty()
+                    .map_err(|source| Error {
+                        submodule: self.clone(),
+                        source: Source::DirtyCheckFailed(source),
+                    })?;
+                (Some(commit), dirty)
+            }
+            // Merge conflicts count as uncommitted changes.
+            Some('U') => (None, true),
+            _ => (None, false),
+        };
+        Ok(SubmoduleStatus {
+            recorded_commit,
+            checked_out_commit,
+            initialized,
+            dirty,
+        })
+    }
+
+    /// Advances the submodule to the tip of its remote-tracking branch,
+    /// returning the hash of the newly checked out commit.
+    pub fn update_remote(&self, git: Git<'_>) -> Result<String, Error> {
+        self.update(
+            git,
+            &UpdateOptions {
+                remote: true,
+                ..Default::default()
+            },
+        )?;
+        crate::repo::Repo::from_path(git.root().join(&self.path))
+            .with_settings_of(git)
+            .head_hash()
+            .map_err(|source| Error {
+                submodule: self.clone(),
+                source: Source::HeadCheckFailed(source),
+            })
+    }
+}
+
+/// Initializes each of `submodules` like [`Submodule::init`] does, but clones
+/// up to `jobs` of them at once. Rather than stopping at the first failure,
+/// this keeps going and returns every error.
+pub fn init_parallel(
+    git: Git<'_>,
+    submodules: &[Submodule],
+    jobs: usize,
+) -> Result<(), Vec<Error>> {
+    let mut errors = Vec::new();
+    // Only one git process can write the index and config at a time, so
+    // everything but the cloning happens up front, one by one.
+    let pending = submodules
+        .iter()
+        .filter(|submodule| match submodule.prepare(git) {
+            Ok(needs_init) => needs_init,
+            Err(err) => {
+                errors.push(err);
+                false
+            }
+        })
+        .collect::<Vec<_>>();
+    if !pending.is_empty() {
+        log::info!(
+            "initializing submodules {:?} with {} jobs",
+            pending
+                .iter()
+                .map(|submodule| submodule.name().unwrap())
+                .collect::<Vec<_>>(),
+            jobs
+        );
+        let mut command = git.command();
+        command
+            .add_parsed_args("submodule update --init --recursive --jobs")
+            .add_arg(jobs.max(1).to_string());
+        if pending.iter().any(|submodule| submodule.shallow) {
+            command.add_arg("--recommend-shallow");
+        }
+        command
+            .add_arg("--")
+            .add_args(pending.iter().map(|submodule| &submodule.path));
+        if let Err(err) = pending[0].clone_with(git, &mut command, None, Source::InitFailed) {
+            // We can't tell which submodule failed, so we go through them
+            // again one at a time. The ones that made it are no-ops, and if
+            // we were cancelled, the rest fail right away.
+            // The error names the first submodule, so only its cause is of
+            // any use.
+            log::warn!(
+                "failed to initialize submodules in parallel: {:?}",
+                err.source
+            );
+            for submodule in pending {
+                log::info!("initializing submodule: {:#?}", submodule);
+                let mut command = git
+                    .command()
+                    .with_parsed_args("submodule update --init --recursive --")
+                    .with_arg(&submodule.path);
+                if let Err(err) = submodule.clone_with(git, &mut command, None, Source::InitFailed)
+                {
+                    errors.push(err);
+                }
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Submodule clones aren't given a timeout, but can still be cancelled and
+/// retried.
+fn clone_limits(git: Git<'_>) -> Limits {
+    Limits {
+        cancel: git.cancellation().cloned(),
+        ..Limits::retrying(git.retry_policy())
+    }
+}
+
+/// Runs `f` on tokio's blocking pool, with `submodule` and `superproject`'s
+/// settings.
+#[cfg(feature = "tokio")]
+async fn on_blocking_pool<T: Send + 'static>(
+    submodule: &Submodule,
+    superproject: &crate::repo::Repo,
+    f: impl FnOnce(&Submodule, Git<'_>) -> T + Send + 'static,
+) -> T {
+    let (submodule, superproject) = (submodule.clone(), superproject.clone());
+    crate::blocking(move || f(&submodule, superproject.git())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{FixtureRepo, TempDir};
+    use std::ffi::OsString;
+
+    /// git won't clone submodules from local paths without this.
+    fn allow_file_protocol() -> Vec<(OsString, OsString)> {
+        vec![(
+            "GIT_CONFIG_PARAMETERS".into(),
+            "'protocol.file.allow=always'".into(),
+        )]
+    }
+
+    /// A repo to use as a submodule, with a single commit.
+    fn dep(dir: &TempDir, name: &str) -> FixtureRepo {
+        let dep = FixtureRepo::new(dir.path().join(name)).unwrap();
+        dep.commit_file("lib", name, "first").unwrap();
+        dep
+    }
+
+    fn superproject(dir: &TempDir) -> FixtureRepo {
+        let superproject = FixtureRepo::new(dir.path().join("superproject")).unwrap();
+        superproject.commit_file("README", "hi", "readme").unwrap();
+        superproject
+    }
+
+    fn submodule_for(dep: &FixtureRepo, name: &str) -> Submodule {
+        Submodule::with_remote_and_path(dep.path().to_str().unwrap(), format!("deps/{}", name))
+            .with_name(name)
+    }
+
+    #[test]
+    fn index_and_init_checks_match_whole_names() {
+        let dir = TempDir::new().unwrap();
+        let superproject = superproject(&dir);
+        let foobar = dep(&dir, "foobar");
+        superproject
+            .add_submodule(&submodule_for(&foobar, "foobar"), "add foobar")
+            .unwrap();
+        // A commented out section doesn't count either.
+        let gitmodules = superproject.path().join(".gitmodules");
+        let mut contents = std::fs::read_to_string(&gitmodules).unwrap();
+        contents.push_str("# [submodule \"foo\"]\n#\tpath = deps/foo\n");
+        std::fs::write(&gitmodules, contents).unwrap();
+
+        let foo = dep(&dir, "foo");
+        let submodule = submodule_for(&foo, "foo");
+        let git = superproject.git();
+        assert!(!submodule.in_index(git, "foo").unwrap());
+        assert!(!submodule.initialized(git, "foo").unwrap());
+        assert!(submodule.in_index(git, "foobar").unwrap());
+        assert!(submodule.initialized(git, "foobar").unwrap());
+        let plan = submodule.plan(git, None).unwrap();
+        assert_eq!(plan.steps, [InitStep::Add, InitStep::Initialize]);
+
+        let env = allow_file_protocol();
+        submodule.init(git.with_env(&env), None).unwrap();
+        assert!(submodule.in_index(git, "foo").unwrap());
+        assert!(submodule.initialized(git, "foo").unwrap());
+        assert!(submodule.plan(git, None).unwrap().is_empty());
+    }
+
+    /// A fresh clone of a superproject with submodules `a` and `b`, neither
+    /// of them initialized.
+    fn clone_with_two_submodules(dir: &TempDir) -> (crate::repo::Repo, [Submodule; 2]) {
+        let superproject = superproject(dir);
+        let submodules = ["a", "b"].map(|name| {
+            let submodule = submodule_for(&dep(dir, name), name);
+            superproject
+                .add_submodule(&submodule, &format!("add {}", name))
+                .unwrap();
+            submodule
+        });
+        let clone = crate::repo::Repo::from_path(dir.path().join("clone"));
+        clone.update(superproject.url()).unwrap();
+        (clone, submodules)
+    }
+
+    #[test]
+    fn init_leaves_other_submodules_alone() {
+        let dir = TempDir::new().unwrap();
+        let (clone, [a, b]) = clone_with_two_submodules(&dir);
+        let env = allow_file_protocol();
+        let git = clone.git().with_env(&env);
+        a.init(git, None).unwrap();
+        assert!(clone.path().join("deps/a/lib").is_file());
+        assert!(!clone.path().join("deps/b/lib").exists());
+        assert!(!b.initialized(git, "b").unwrap());
+
+        b.init_all(git, None).unwrap();
+        assert!(clone.path().join("deps/b/lib").is_file());
+    }
+
+    #[test]
+    fn name_is_inferred_from_last_url_segment() {
+        for (remote, name) in [
+            ("https://github.com/org/my-repo.git", Some("my-repo")),
+            ("https://github.com/org/my-repo", Some("my-repo")),
+            ("https://github.com/org/my-repo.git/", Some("my-repo")),
+            ("https://github.com/org/my-repo/", Some("my-repo")),
+            ("ssh://git@github.com/org/rust-sdl2.git", Some("rust-sdl2")),
+            ("ssh://git@github.com:22/org/repo", Some("repo")),
+            ("git@github.com:org/repo", Some("repo")),
+            ("git@github.com:org/lib.v2.git", Some("lib.v2")),
+            ("git@github.com:repo.git", Some("repo")),
+            ("file:///home/me/src/dep_1.git", Some("dep_1")),
+            ("file:///home/me/src/dep", Some("dep")),
+            ("../sibling.git", Some("sibling")),
+            ("https://github.com/org/.git", None),
+            ("https://github.com/org/..", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                Submodule::with_remote_and_path(remote, "deps/x").name(),
+                name,
+                "{:?}",
+                remote
+            );
+        }
+        let named = Submodule::with_remote_and_path("git@github.com:org/repo", "deps/x")
+            .with_name("custom");
+        assert_eq!(named.name(), Some("custom"));
+    }
+
+    #[test]
+    fn init_fetches_commits_newer_than_the_clone() {
+        let dir = TempDir::new().unwrap();
+        let (clone, [a, _]) = clone_with_two_submodules(&dir);
+        let env = allow_file_protocol();
+        let git = clone.git().with_env(&env);
+        a.init(git, None).unwrap();
+        let submodule = crate::repo::Repo::from_path(clone.path().join("deps/a"));
+
+        // Re-pinning to a commit the submodule's never seen fetches it first.
+        let upstream = FixtureRepo::new(dir.path().join("a")).unwrap();
+        let newer = upstream.commit_file("lib", "2", "second").unwrap();
+        assert!(!submodule.contains_commit(&newer).unwrap());
+        a.init(git, Some(&newer)).unwrap();
+        assert_eq!(submodule.head_hash().unwrap(), newer);
+
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        let err = a.init(git, Some(missing)).unwrap_err();
+        assert!(
+            matches!(&err.source, Source::CommitUnavailable { commit } if commit == missing),
+            "{:?}",
+            err
+        );
+        assert_eq!(submodule.head_hash().unwrap(), newer);
+
+        submodule
+            .git()
+            .command_parse("remote set-url origin")
+            .with_arg(dir.path().join("nowhere"))
+            .run_and_wait_for_output()
+            .unwrap();
+        let newest = upstream.commit_file("lib", "3", "third").unwrap();
+        let err = a.init(git, Some(&newest)).unwrap_err();
+        assert!(matches!(err.source, Source::FetchFailed(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn init_handles_whatever_is_already_at_the_path() {
+        let dir = TempDir::new().unwrap();
+        let superproject = superproject(&dir);
+        let git = superproject.git();
+        let env = allow_file_protocol();
+
+        let vendored = submodule_for(&dep(&dir, "vendored"), "vendored");
+        let path = superproject.path().join("deps/vendored");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("copy"), "old").unwrap();
+        let err = vendored.init(git.with_env(&env), None).unwrap_err();
+        assert!(matches!(err.source, Source::PathOccupied), "{:?}", err);
+        assert!(path.join("copy").is_file());
+        assert!(!vendored.in_index(git, "vendored").unwrap());
+
+        let vendored = vendored.with_replace_existing(true);
+        assert_eq!(
+            vendored.plan(git, None).unwrap().steps,
+            [InitStep::MoveAside, InitStep::Add, InitStep::Initialize]
+        );
+        vendored.init(git.with_env(&env), None).unwrap();
+        assert!(path.join("lib").is_file());
+        assert_eq!(
+            std::fs::read_to_string(superproject.path().join("deps/vendored.orig/copy")).unwrap(),
+            "old"
+        );
+
+        // git itself is fine with an empty directory, so we are too.
+        let empty = submodule_for(&dep(&dir, "empty"), "empty");
+        std::fs::create_dir_all(superproject.path().join("deps/empty")).unwrap();
+        assert_eq!(
+            empty.plan(git, None).unwrap().steps,
+            [InitStep::Add, InitStep::Initialize]
+        );
+        empty.init(git.with_env(&env), None).unwrap();
+        assert!(superproject.path().join("deps/empty/lib").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn init_adds_submodules_at_non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt as _};
+
+        let dir = TempDir::new().unwrap();
+        let superproject = superproject(&dir);
+        let dep = dep(&dir, "cafe");
+        let path = Path::new(OsStr::from_bytes(b"deps/caf\xe9"));
+        assert!(path.to_str().is_none());
+        let submodule =
+            Submodule::with_remote_and_path(dep.path().to_str().unwrap(), path).with_name("cafe");
+        let env = allow_file_protocol();
+        let git = superproject.git();
+        submodule.init(git.with_env(&env), None).unwrap();
+        assert!(superproject.path().join(path).join("lib").is_file());
+        assert!(submodule.in_index(git, "cafe").unwrap());
+        assert!(submodule.plan(git, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn checks_work_from_a_linked_worktree() {
+        let dir = TempDir::new().unwrap();
+        let (clone, [a, _]) = clone_with_two_submodules(&dir);
+        let env = allow_file_protocol();
+        a.init(clone.git().with_env(&env), None).unwrap();
+        let worktree = clone
+            .add_worktree(&dir.path().join("worktree"), "HEAD")
+            .unwrap();
+        let git = worktree.git();
+
+        let canonical = |path: PathBuf| path.canonicalize().unwrap();
+        let common_dir = canonical(clone.path().join(".git"));
+        assert_eq!(canonical(clone.git().git_dir().unwrap()), common_dir);
+        assert_eq!(canonical(git.common_dir().unwrap()), common_dir);
+        let git_dir = canonical(git.git_dir().unwrap());
+        assert_ne!(git_dir, common_dir);
+        assert!(git_dir.starts_with(&common_dir));
+
+        // The URL's already in the shared config, but the worktree still
+        // needs its own checkout.
+        assert!(a.in_index(git, "a").unwrap());
+        assert!(a.initialized(git, "a").unwrap());
+        assert_eq!(a.plan(git, None).unwrap().steps, [InitStep::Initialize]);
+        a.init(git.with_env(&env), None).unwrap();
+        assert!(worktree.path().join("deps/a/lib").is_file());
+        assert!(a.plan(git, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn foreach_reports_nested_listing_failures_per_submodule() {
+        let dir = TempDir::new().unwrap();
+        let (clone, [a, b]) = clone_with_two_submodules(&dir);
+        let env = allow_file_protocol();
+        let git = clone.git().with_env(&env);
+        a.init(git, None).unwrap();
+        b.init(git, None).unwrap();
+        // `a` has a `.gitmodules` nobody can parse.
+        std::fs::write(clone.path().join("deps/a/.gitmodules"), "[submodule\n").unwrap();
+
+        let visit = |name: &str, git: Git<'_>| -> Result<String, crate::repo::Error> {
+            Ok(format!("{} at {:?}", name, git.root().file_name().unwrap()))
+        };
+        let results = clone.submodule_foreach(false, visit).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        let results = clone.submodule_foreach(true, visit).unwrap();
+        let names = results
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert!(
+            matches!(
+                &results[0].1,
+                Err(crate::repo::Error::SubmoduleListFailed(_))
+            ),
+            "{:?}",
+            results[0]
+        );
+        assert_eq!(results[1].1.as_deref().unwrap(), "b at \"b\"");
+
+        // There's nothing to report it against at the top level, though.
+        std::fs::write(clone.path().join(".gitmodules"), "[submodule\n").unwrap();
+        assert!(clone.submodule_foreach(true, visit).is_err());
+    }
 }
@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't run {program:?}; make sure git is installed and on your `PATH`.")]
+    GitNotFound { program: PathBuf },
+    #[error("Failed to get git's version: {0}")]
+    VersionFailed(#[source] bossy::Error),
+    #[error("`git --version` printed {0:?}, which doesn't contain a version.")]
+    VersionParseFailed(String),
+    #[error("git {found} is installed, but {required} or newer is required.")]
+    GitTooOld {
+        found: GitVersion,
+        required: GitVersion,
+    },
+}
+
+/// A git release, like `2.39.5`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GitVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Display for GitVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl GitVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the output of `git --version`, which looks like
+    /// "git version 2.39.5", or "git version 2.39.3 (Apple Git-146)" on macOS,
+    /// or "git version 2.41.0.windows.1" on Windows.
+    fn parse(output: &str) -> Option<Self> {
+        let version = output.trim().strip_prefix("git version ")?;
+        let mut parts = version
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|part| part.parse());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().and_then(Result::ok).unwrap_or_default();
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+/// The version of the git on `PATH`. This only runs `git --version` the first
+/// time it succeeds.
+pub fn git_version() -> Result<GitVersion, Error> {
+    version_of(Path::new("git"))
+}
+
+/// Like [`git_version`], but for a specific git executable.
+pub(crate) fn version_of(program: &Path) -> Result<GitVersion, Error> {
+    static VERSIONS: OnceLock<Mutex<HashMap<PathBuf, GitVersion>>> = OnceLock::new();
+    let versions = VERSIONS.get_or_init(Default::default);
+    if let Some(version) = versions.lock().unwrap().get(program) {
+        return Ok(*version);
+    }
+    let output = bossy::Command::impure(program)
+        .with_arg("--version")
+        .run_and_wait_for_str(ToOwned::to_owned)
+        .map_err(|err| {
+            let not_found = std::error::Error::source(&err)
+                .and_then(|source| source.downcast_ref::<std::io::Error>())
+                .map(|source| source.kind() == std::io::ErrorKind::NotFound)
+                .unwrap_or_default();
+            if not_found {
+                Error::GitNotFound {
+                    program: program.to_owned(),
+                }
+            } else {
+                Error::VersionFailed(err)
+            }
+        })?;
+    let version = GitVersion::parse(&output).ok_or(Error::VersionParseFailed(output))?;
+    versions.lock().unwrap().insert(program.to_owned(), version);
+    Ok(version)
+}
+
+/// Fails with [`Error::GitNotFound`] or [`Error::GitTooOld`] unless git
+/// `required` or newer is installed.
+pub fn ensure_git(required: GitVersion) -> Result<GitVersion, Error> {
+    ensure_version_of(Path::new("git"), required)
+}
+
+/// Like [`ensure_git`], but for a specific git executable.
+pub(crate) fn ensure_version_of(program: &Path, required: GitVersion) -> Result<GitVersion, Error> {
+    let found = version_of(program)?;
+    if found < required {
+        Err(Error::GitTooOld { found, required })
+    } else {
+        Ok(found)
+    }
+}
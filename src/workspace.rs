@@ -0,0 +1,101 @@
+use crate::{
+    progress::ProgressEvent,
+    repo::{Error, Repo, Status, UpdateOutcome},
+};
+use std::{
+    iter::FromIterator,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// A set of repos along with the URLs they're cloned from, for checking or
+/// updating them all at once.
+///
+/// The `*_all` methods run up to `jobs` repos at a time (at least one), and
+/// return a result for every repo in the order they were added, rather than
+/// stopping at the first failure.
+#[derive(Clone, Debug, Default)]
+pub struct Workspace {
+    repos: Vec<(Repo, String)>,
+}
+
+impl FromIterator<(Repo, String)> for Workspace {
+    fn from_iter<I: IntoIterator<Item = (Repo, String)>>(iter: I) -> Self {
+        Self {
+            repos: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_repo(mut self, repo: Repo, url: impl Into<String>) -> Self {
+        self.repos.push((repo, url.into()));
+        self
+    }
+
+    pub fn repos(&self) -> &[(Repo, String)] {
+        &self.repos
+    }
+
+    /// Calls `f` for every repo, spread over up to `jobs` threads.
+    fn run_all<T: Send>(&self, jobs: usize, f: impl Fn(&Repo, &str) -> T + Sync) -> Vec<(Repo, T)> {
+        let next = AtomicUsize::new(0);
+        let results = self
+            .repos
+            .iter()
+            .map(|_| Mutex::new(None))
+            .collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.clamp(1, self.repos.len().max(1)) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let (repo, url) = match self.repos.get(index) {
+                        Some(entry) => entry,
+                        None => break,
+                    };
+                    *results[index].lock().unwrap() = Some(f(repo, url));
+                });
+            }
+        });
+        self.repos
+            .iter()
+            .zip(results)
+            .map(|((repo, _), result)| {
+                let result = result
+                    .into_inner()
+                    .unwrap()
+                    .expect("developer error: repo was never visited");
+                (repo.clone(), result)
+            })
+            .collect()
+    }
+
+    /// [`Repo::status`] for every repo.
+    pub fn status_all(&self, jobs: usize) -> Vec<(Repo, Result<Status, Error>)> {
+        self.run_all(jobs, |repo, _| repo.status())
+    }
+
+    /// [`Repo::update`] for every repo, from its URL.
+    pub fn update_all(&self, jobs: usize) -> Vec<(Repo, Result<UpdateOutcome, Error>)> {
+        self.run_all(jobs, |repo, url| repo.update(url))
+    }
+
+    /// The same as [`Workspace::update_all`], but calls `progress` with each
+    /// repo's clone or fetch progress, along with the repo it's for. This is
+    /// called from several threads at once when `jobs` is more than one.
+    pub fn update_all_with_progress(
+        &self,
+        jobs: usize,
+        progress: impl Fn(&Repo, ProgressEvent) + Sync,
+    ) -> Vec<(Repo, Result<UpdateOutcome, Error>)> {
+        self.run_all(jobs, |repo, url| {
+            repo.update_with_progress(url, |event| progress(repo, event))
+        })
+    }
+}